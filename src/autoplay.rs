@@ -0,0 +1,129 @@
+use crate::permissions::is_dj;
+use crate::player_controller::PlayerController;
+use crate::util::TrackUserData;
+use crate::*;
+use lavalink_rs::model::track::{TrackData, TrackInfo};
+use std::collections::HashSet;
+
+/// How many related tracks are queued whenever the queue runs out.
+const AUTOPLAY_BATCH: usize = 3;
+
+fn youtube_mix(video_id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={video_id}&list=RD{video_id}")
+}
+
+/// A query for tracks related to `info`, if its source has recommendations.
+fn related_query(info: &TrackInfo) -> Option<String> {
+    match info.source_name.as_str() {
+        "youtube" => Some(youtube_mix(&info.identifier)),
+        "spotify" => Some(format!("mix:track:{}", info.identifier)),
+        _ => info.isrc.as_ref().map(|isrc| format!("mix:isrc:{isrc}")),
+    }
+}
+
+/// Identifies a track across sources, since the same song might come back from a different one.
+fn track_key(info: &TrackInfo) -> (String, String) {
+    (info.author.to_lowercase(), info.title.to_lowercase())
+}
+
+impl PlayerController {
+    /// Queues tracks related to `seed`, if autoplay is on and nothing else is queued.
+    pub async fn autoplay_if_drained(&self, seed: &TrackData) -> Result<()> {
+        let guild_id = self.data.guild_id;
+        if !settings::get(guild_id).await.autoplay() {
+            return Ok(());
+        }
+        if self.ctx.get_queue().get_count().await? > 0 {
+            return Ok(());
+        }
+        // Don't autoplay off e.g. the join announcement
+        let Ok(seed_data) = TrackUserData::try_from(seed) else {
+            return Ok(());
+        };
+
+        let recent: HashSet<_> = history::entries(guild_id)
+            .iter()
+            .map(|entry| track_key(&entry.track.info))
+            .chain([track_key(&seed.info)])
+            .collect();
+        let picks: Vec<_> = self
+            .related_tracks(seed)
+            .await?
+            .into_iter()
+            .filter(|t| !recent.contains(&track_key(&t.info)))
+            .take(AUTOPLAY_BATCH)
+            .collect();
+        if picks.is_empty() {
+            debug!(
+                "No related tracks to autoplay after {}",
+                seed.info.identifier
+            );
+            return Ok(());
+        }
+
+        let user_data = TrackUserData {
+            autoplay: true,
+            ..TrackUserData::new(
+                seed_data.requester_id,
+                format!("autoplay {}", seed.info.identifier),
+                guild_id,
+            )
+        };
+        self.enqueue_tracks(picks, user_data).await
+    }
+
+    async fn related_tracks(&self, seed: &TrackData) -> Result<Vec<TrackData>> {
+        let query = match related_query(&seed.info) {
+            Some(query) => query,
+            None => {
+                // Find the track on YouTube to borrow its mix
+                let term = format!("{} {}", seed.info.author, seed.info.title);
+                let results = self.search_single(&term, &SearchEngines::YouTube).await?;
+                let Some(found) = results.first() else {
+                    return Ok(vec![]);
+                };
+                youtube_mix(&found.info.identifier)
+            }
+        };
+
+        Ok(match self.load_or_search(&query).await? {
+            TrackLoadData::Playlist(playlist) => playlist.tracks,
+            TrackLoadData::Search(results) => results,
+            TrackLoadData::Track(track) => vec![track],
+            TrackLoadData::Error(_) => vec![],
+        })
+    }
+}
+
+/// Keep playing related tracks when the queue runs out.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn autoplay(
+    ctx: Context<'_>,
+    #[description = "Turn autoplay on or off, toggles if left out"] enabled: Option<bool>,
+) -> Result<(), Error> {
+    if !is_dj(ctx).await? {
+        user_error!("Only DJs can do that")
+    }
+
+    let guild_id = ctx.guild_id().unwrap();
+    let enabled = enabled.unwrap_or(!settings::get(guild_id).await.autoplay());
+    settings::update(guild_id, |s| s.autoplay = Some(enabled)).await?;
+
+    if enabled {
+        ctx.say("Autoplay on, related tracks will play when the queue runs out")
+            .await?;
+    } else {
+        ctx.say("Autoplay off").await?;
+    }
+
+    // Fill the queue right away if it already ran out
+    let player = ctx.data().lavalink.get_player_context(guild_id);
+    if let (true, Some(player)) = (enabled, player) {
+        let controller = PlayerController::from(player);
+        if let Some(current) = controller.ctx.get_player().await?.track {
+            controller.autoplay_if_drained(&current).await?;
+        }
+    }
+
+    Ok(())
+}
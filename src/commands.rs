@@ -1,26 +1,150 @@
-use std::time::Duration;
-
-use crate::player_controller::PlayerController;
+use crate::history::TrackEnding;
+use crate::player_controller::{LoopMode, PlayerController, PlayerData};
 use crate::status::StatusBuilder;
-use crate::track_loading::PREFERRED_SEARCH_ENGINES;
-use crate::util::{check_if_in_channel, source_to_emoji, TrackUserData};
+use crate::util::{check_if_in_channel, TrackUserData};
 use crate::*;
 use crate::{util, Error};
 use lavalink_rs::model::track::TrackData;
 use poise::serenity_prelude as serenity;
-use poise::serenity_prelude::{ButtonStyle, CreateActionRow, CreateButton, CreateMessage};
-use poise::CreateReply;
+use poise::{ChoiceParameter, CreateReply};
 use rand::seq::SliceRandom;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+
+/// A 1-based, inclusive range of tracks like `3-7`, or `5-` for everything from track 5 on.
+#[derive(Clone, Copy)]
+pub struct TrackRange {
+    start: usize,
+    end: Option<usize>,
+}
+
+impl FromStr for TrackRange {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.split_once('-') {
+            Some((start, "")) => TrackRange {
+                start: start.trim().parse()?,
+                end: None,
+            },
+            Some((start, end)) => TrackRange {
+                start: start.trim().parse()?,
+                end: Some(end.trim().parse()?),
+            },
+            None => {
+                let position = s.trim().parse()?;
+                TrackRange {
+                    start: position,
+                    end: Some(position),
+                }
+            }
+        })
+    }
+}
+
+impl TrackRange {
+    fn slice(self, mut tracks: Vec<TrackData>) -> Result<Vec<TrackData>> {
+        let len = tracks.len();
+        let end = self.end.unwrap_or(len).min(len);
+        if self.start == 0 || self.start > end {
+            user_error!("The range has to be within 1-{len}, like 3-7")
+        }
+        tracks.truncate(end);
+        Ok(tracks.split_off(self.start - 1))
+    }
+}
+
+/// How `/play` adds what it loaded. Prefix commands spell these out in front of the search term, like
+/// `!play --shuffle range:3-7 <url>`, so searches starting with e.g. "next" or a number stay intact.
+#[derive(Default)]
+struct PlayOptions {
+    shuffle: bool,
+    reverse: bool,
+    next: bool,
+    range: Option<TrackRange>,
+}
+
+impl PlayOptions {
+    /// Takes the options off the front of `term`, returning them and the rest.
+    fn strip_from(term: &str) -> Result<(Self, &str)> {
+        let mut options = PlayOptions::default();
+        let mut rest = term.trim_start();
+        loop {
+            let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            match word {
+                "--shuffle" => options.shuffle = true,
+                "--reverse" => options.reverse = true,
+                "--next" => options.next = true,
+                _ => {
+                    let Some(range) = word.strip_prefix("range:") else {
+                        break;
+                    };
+                    let Ok(range) = range.parse() else {
+                        user_error!("\"{range}\" isn't a range, try range:3-7 or range:5-")
+                    };
+                    options.range = Some(range);
+                }
+            }
+            rest = after.trim_start();
+        }
+        Ok((options, rest))
+    }
+}
 
 /// Play a song in the voice channel you are connected in.
-#[poise::command(slash_command, prefix_command)]
+#[poise::command(slash_command)]
 pub async fn play(
     ctx: Context<'_>,
-    #[description = "Search term or URL"]
+    #[description = "Shuffle the playlist as it is added"]
+    #[flag]
+    shuffle: bool,
+    #[description = "Add the playlist in reverse"]
+    #[flag]
+    reverse: bool,
+    #[description = "Play after the current track instead of at the end of the queue"]
+    #[flag]
+    next: bool,
+    #[description = "Only add these playlist tracks, like 3-7 or 5-"] range: Option<TrackRange>,
+    #[description = "Search term or URL"] term: Option<String>,
+) -> Result<(), Error> {
+    let options = PlayOptions {
+        shuffle,
+        reverse,
+        next,
+        range,
+    };
+    play_with(ctx, term, options).await
+}
+
+/// Play a song in the voice channel you are connected in. Put `--shuffle`, `--reverse`, `--next` or a range like
+/// `range:3-7` in front of the search term to change how it's added.
+///
+/// Prefix commands are found by name or alias and slash commands only by name, so this has to come before [`play`] in
+/// the command list.
+#[poise::command(prefix_command, aliases("play"))]
+pub async fn play_prefix(
+    ctx: Context<'_>,
+    #[description = "Options and search term or URL"]
     #[rest]
     term: Option<String>,
 ) -> Result<(), Error> {
+    let (options, term) = PlayOptions::strip_from(term.as_deref().unwrap_or_default())?;
+    let term = (!term.is_empty()).then(|| term.to_string());
+    play_with(ctx, term, options).await
+}
+
+async fn play_with(ctx: Context<'_>, term: Option<String>, options: PlayOptions) -> Result<()> {
+    let PlayOptions {
+        shuffle,
+        reverse,
+        next,
+        range,
+    } = options;
     let guild_id = ctx.guild_id().unwrap();
+    if ctx.data().lavalink.get_player_context(guild_id).is_some() {
+        permissions::require_same_channel(ctx).await?;
+    }
     let player_ctx = util::join(&ctx, guild_id, None).await?;
 
     let Some(query) = term else {
@@ -38,56 +162,245 @@ pub async fn play(
     };
 
     let controller = PlayerController::from(player_ctx);
-    let mut playlist_info = None;
-    let mut tracks: Vec<TrackData> = vec![];
 
-    match controller.load_or_search(&query).await? {
-        TrackLoadData::Track(x) => tracks.push(x),
+    let (reply, tracks) = match controller.load_or_search(&query).await? {
+        TrackLoadData::Track(x) => {
+            let reply = CreateReply::default().embed(messages::added_to_queue(&x));
+            (reply, vec![x])
+        }
         TrackLoadData::Search(x) => {
             let first = x.first().ok_or_else(|| anyhow!("No search results"))?;
-            tracks.push(first.clone())
+            let reply = CreateReply::default().embed(messages::added_to_queue(first));
+            (reply, vec![first.clone()])
         }
         TrackLoadData::Playlist(x) => {
-            playlist_info = Some(x.info);
-            tracks = x.tracks
+            let total = x.tracks.len();
+            let mut notes = vec![];
+            let selected = usize::try_from(x.info.selected_track)
+                .ok()
+                .filter(|&i| i < total);
+
+            // The selected track (e.g. from a YouTube link with &index=) stays first, the rest may be rearranged
+            let (mut tracks, pinned) = match (range, selected) {
+                (Some(range), _) => {
+                    let tracks = range.slice(x.tracks)?;
+                    let end = range.start + tracks.len() - 1;
+                    notes.push(format!("tracks {}-{end} of {total}", range.start));
+                    (tracks, 0)
+                }
+                (None, Some(selected)) => {
+                    let mut tracks = x.tracks;
+                    notes.push(format!("starting at track {}", selected + 1));
+                    // The tracks before it come after the last one, so none are left out
+                    tracks.rotate_left(selected);
+                    (tracks, 1)
+                }
+                (None, None) => (x.tracks, 0),
+            };
+            if tracks.is_empty() {
+                user_error!("The playlist is empty")
+            }
+
+            let rearranged = &mut tracks[pinned..];
+            if shuffle {
+                rearranged.shuffle(&mut rand::rng());
+                notes.push("shuffled".into());
+            }
+            if reverse {
+                rearranged.reverse();
+                notes.push("reversed".into());
+            }
+            if next {
+                notes.push("playing next".into());
+            }
+
+            let embed = messages::playlist_added(&x.info, &x.plugin_info, &tracks, &notes);
+            (CreateReply::default().embed(embed), tracks)
         }
         TrackLoadData::Error(_) => {
             unreachable!("TrackLoadData::Error should be handled while loading/searching tracks")
         }
     };
+    ctx.send(reply).await?;
 
-    if let Some(info) = playlist_info {
-        ctx.say(format!("Added playlist to queue: {}", info.name,))
-            .await?;
+    let user_data = TrackUserData::new(ctx.author().id, query, guild_id);
+    if next {
+        controller.enqueue_tracks_next(tracks, user_data).await?;
     } else {
-        ctx.send(CreateReply::default().embed(messages::added_to_queue(&tracks[0])))
-            .await?;
+        controller.enqueue_tracks(tracks, user_data).await?;
     }
+    Ok(())
+}
 
-    let user_data = TrackUserData::new(ctx.author().id, query, guild_id);
+/// Tracks for `/playnext` and `/playnow`: the first search result, or everything that was loaded.
+async fn load_priority_tracks(
+    controller: &PlayerController,
+    query: &str,
+) -> Result<Vec<TrackData>> {
+    Ok(match controller.load_or_search(query).await? {
+        TrackLoadData::Track(x) => vec![x],
+        TrackLoadData::Search(x) => match x.into_iter().next() {
+            Some(first) => vec![first],
+            None => user_error!("No search results for \"{query}\""),
+        },
+        TrackLoadData::Playlist(x) => x.tracks,
+        TrackLoadData::Error(_) => {
+            unreachable!("TrackLoadData::Error should be handled while loading/searching tracks")
+        }
+    })
+}
 
-    controller.enqueue_tracks(tracks, user_data).await?;
+fn priority_reply(tracks: &[TrackData], description: &str) -> CreateReply {
+    match tracks {
+        [track] => {
+            CreateReply::default().embed(messages::added_to_queue(track).description(description))
+        }
+        tracks => CreateReply::default().content(format!("{description}: {} tracks", tracks.len())),
+    }
+}
+
+/// Play a song right after the current one.
+#[poise::command(slash_command, prefix_command)]
+pub async fn playnext(
+    ctx: Context<'_>,
+    #[description = "Search term or URL"]
+    #[rest]
+    term: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if ctx.data().lavalink.get_player_context(guild_id).is_some() {
+        permissions::require_dj(ctx).await?;
+    }
+    let controller = PlayerController::from(util::join(&ctx, guild_id, None).await?);
+
+    let tracks = load_priority_tracks(&controller, &term).await?;
+    if tracks.is_empty() {
+        user_error!("Nothing to play for \"{term}\"")
+    }
+    ctx.send(priority_reply(&tracks, "Playing next")).await?;
+
+    let user_data = TrackUserData::new(ctx.author().id, term, guild_id);
+    controller.enqueue_tracks_next(tracks, user_data).await?;
+    Ok(())
+}
+
+/// Play a song immediately, continuing the current one afterwards.
+#[poise::command(slash_command, prefix_command)]
+pub async fn playnow(
+    ctx: Context<'_>,
+    #[description = "Search term or URL"]
+    #[rest]
+    term: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if ctx.data().lavalink.get_player_context(guild_id).is_some() {
+        permissions::require_dj(ctx).await?;
+    }
+    let controller = PlayerController::from(util::join(&ctx, guild_id, None).await?);
+
+    let tracks = load_priority_tracks(&controller, &term).await?;
+    if tracks.is_empty() {
+        user_error!("Nothing to play for \"{term}\"")
+    }
+    ctx.send(priority_reply(&tracks, "Playing now")).await?;
+
+    let user_data = TrackUserData::new(ctx.author().id, term, guild_id);
+    let player = controller.ctx.get_player().await?;
+    let Some(current) = player.track.clone().filter(|t| util::is_requested(Some(t))) else {
+        // The join announcement just makes way
+        controller.enqueue_tracks_next(tracks, user_data).await?;
+        return Ok(());
+    };
+
+    // Put the interrupted track back after the new ones, keeping its user data
+    controller
+        .remove_loop_copy(controller.data.loop_mode(), &current)
+        .await?;
+    history::record_end(controller.data.guild_id, &current, TrackEnding::Interrupted);
+    let mut interrupted = TrackInQueue::from(current);
+    interrupted.start_time = Some(Duration::from_millis(seek::current_position(&player)));
+    let mut tracks = util::with_user_data(tracks, &user_data)?;
+    tracks.push_back(interrupted);
+    controller
+        .edit_user_queue(|queue| {
+            for track in tracks.into_iter().rev() {
+                queue.push_front(track);
+            }
+            Ok(())
+        })
+        .await?;
+    controller.ctx.skip()?;
     Ok(())
 }
 
-/// Join the specified voice channel or the one you are currently in.
+/// Join the specified voice channel or the one you are currently in, moving over if already connected.
 #[poise::command(slash_command, prefix_command)]
 pub async fn join(
     ctx: Context<'_>,
     #[description = "The channel ID to join to."]
-    #[channel_types("Voice")]
+    #[channel_types("Voice", "Stage")]
     channel_id: Option<serenity::ChannelId>,
 ) -> Result<(), Error> {
     let guild_id = ctx.guild_id().unwrap();
 
-    util::join(&ctx, guild_id, channel_id).await?;
+    match ctx.data().lavalink.get_player_context(guild_id) {
+        Some(player) if PlayerController::from(player.clone()).is_connected() => {
+            move_bot_to(ctx, player, channel_id).await?
+        }
+        _ => {
+            util::join(&ctx, guild_id, channel_id).await?;
+        }
+    }
 
     Ok(())
 }
 
+/// Move the bot to the specified voice channel or the one you are currently in, keeping the queue.
+#[poise::command(slash_command, prefix_command, rename = "move-bot")]
+pub async fn move_bot(
+    ctx: Context<'_>,
+    #[description = "The channel ID to move to."]
+    #[channel_types("Voice", "Stage")]
+    channel_id: Option<serenity::ChannelId>,
+) -> Result<(), Error> {
+    let player = check_if_in_channel(ctx).await?;
+    move_bot_to(ctx, player, channel_id).await
+}
+
+/// Moves the player to `channel_id`, or the invoker's channel. Only DJs may, unless nobody is listening.
+async fn move_bot_to(
+    ctx: Context<'_>,
+    player: PlayerContext,
+    channel_id: Option<serenity::ChannelId>,
+) -> Result<()> {
+    let (target, current, anyone_listening) = {
+        let guild = ctx.guild().with_context(|| "Guild isn't cached")?;
+        let current = permissions::voice_channel_of(&guild, ctx.cache().current_user().id);
+        (
+            channel_id.or_else(|| permissions::voice_channel_of(&guild, ctx.author().id)),
+            current,
+            current.is_some_and(|c| !permissions::humans_in_channel(&guild, c).is_empty()),
+        )
+    };
+    let Some(target) = target else {
+        user_error!("Not in a voice channel!")
+    };
+    if current == Some(target) {
+        user_error!("I'm already in <#{target}>")
+    }
+    if anyone_listening && !permissions::is_dj(ctx).await? {
+        user_error!("Only DJs can move me while people are listening")
+    }
+
+    PlayerController::from(player).move_to(target).await?;
+    ctx.say(format!("Moved to <#{target}>")).await?;
+    Ok(())
+}
+
 /// Leave the current voice channel.
 #[poise::command(slash_command, prefix_command)]
 pub async fn leave(ctx: Context<'_>) -> Result<(), Error> {
+    permissions::require_dj(ctx).await?;
     let guild_id = ctx.guild_id().unwrap();
     let songbird = songbird::get(ctx.serenity_context()).await.unwrap().clone();
     let lavalink = ctx.data().lavalink.clone();
@@ -100,16 +413,54 @@ pub async fn leave(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-/// Add a song to the queue
+/// Show the queue, optionally only tracks matching a title, artist or requester.
 #[poise::command(slash_command, prefix_command)]
-pub async fn queue(ctx: Context<'_>) -> Result<(), Error> {
+pub async fn queue(
+    ctx: Context<'_>,
+    #[description = "Only show tracks whose title, artist or requester contains this"]
+    #[rest]
+    filter: Option<String>,
+) -> Result<(), Error> {
     let player = check_if_in_channel(ctx).await?;
+    let filter = filter.map(|f| f.to_lowercase());
+
+    let now_playing = messages::now_playing_line(&player.get_player().await?)?;
+    let (queue, _) = PlayerController::from(player).user_queue().await?;
+    let lines: Vec<_> = queue
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| {
+            filter
+                .as_deref()
+                .is_none_or(|f| track_matches(ctx, &t.track, f))
+        })
+        .map(|(i, t)| messages::queue_line(i, &t.track))
+        .collect();
 
-    ctx.say(messages::queue_message(player).await?).await?;
+    let header = match &filter {
+        Some(f) => format!("{now_playing}\n-# {} tracks matching \"{f}\"", lines.len()),
+        None => now_playing,
+    };
+    pagination::paginate(ctx, messages::queue_pages(&header, &lines), 0).await?;
 
     Ok(())
 }
 
+/// Whether `filter` (lowercase) is part of the track's title, artist or requester name.
+fn track_matches(ctx: Context<'_>, track: &TrackData, filter: &str) -> bool {
+    let info = &track.info;
+    if info.title.to_lowercase().contains(filter) || info.author.to_lowercase().contains(filter) {
+        return true;
+    }
+
+    let Ok(user_data) = TrackUserData::try_from(track) else {
+        return false;
+    };
+    ctx.cache().user(user_data.requester_id.0).is_some_and(|u| {
+        u.name.to_lowercase().contains(filter) || u.display_name().to_lowercase().contains(filter)
+    })
+}
+
 /// Print the current status (Playing Song + Queue).
 #[poise::command(slash_command, prefix_command)]
 pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
@@ -125,91 +476,21 @@ pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-/// Add a song to the queue
-#[poise::command(slash_command, prefix_command)]
-pub async fn search(
-    ctx: Context<'_>,
-    #[description = "Search term"]
-    #[rest]
-    term: String,
-) -> Result<(), Error> {
-    let guild_id = ctx.guild_id().unwrap();
-    util::join(&ctx, guild_id, None).await?;
-    let player_ctx = check_if_in_channel(ctx).await?;
-    let controller = PlayerController::from(player_ctx);
-
-    let results: Vec<Vec<TrackData>> = controller
-        .search_multiple(&term, &PREFERRED_SEARCH_ENGINES)
-        .await
-        .into_iter()
-        .filter_map(|r| r.ok())
-        .map(|v| v.iter().take(3).cloned().collect())
-        .collect();
-
-    let mut action_rows = vec![];
-    let mut i = 0;
-    for source in &results {
-        let mut buttons = vec![];
-        for track in source {
-            buttons.push(
-                CreateButton::new(i.to_string())
-                    .label((i + 1).to_string())
-                    .emoji(source_to_emoji(&track.info.source_name))
-                    .style(ButtonStyle::Secondary),
-            );
-            i += 1;
-        }
-        if !buttons.is_empty() {
-            action_rows.push(CreateActionRow::Buttons(buttons));
-        }
-    }
-
-    let m = ctx
-        .channel_id()
-        .send_message(
-            &ctx,
-            CreateMessage::new()
-                .embed(messages::search_results(&results))
-                .components(action_rows),
-        )
-        .await?;
-
-    let interaction = match m
-        .await_component_interaction(&ctx.serenity_context().shard)
-        .timeout(Duration::from_secs(60))
-        .await
-    {
-        Some(x) => x,
-        None => {
-            m.delete(&ctx).await?;
-            return Ok(());
-        }
-    };
-
-    let track = results
-        .into_iter()
-        .flatten()
-        .nth(interaction.data.custom_id.parse::<usize>()?)
-        .unwrap();
-
-    m.delete(&ctx).await?;
-    ctx.send(CreateReply::default().embed(messages::added_to_queue(&track)))
-        .await?;
-
-    let user_data = TrackUserData::new(ctx.author().id, term, guild_id);
-    controller.enqueue_tracks([track], user_data).await?;
-
-    Ok(())
-}
-
 /// Skip the current song.
 #[poise::command(slash_command, prefix_command)]
 pub async fn skip(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+    let player = permissions::require_same_channel(ctx).await?;
 
     let now_playing = player.get_player().await?.track;
     if let Some(np) = now_playing {
-        player.skip()?;
+        // Everyone else votes. Someone alone with the bot wins that vote right away.
+        let may_skip =
+            permissions::is_requester(ctx, &np) || permissions::is_admin_or_dj_role(ctx).await?;
+        if !may_skip {
+            return vote_skip::vote_skip(ctx, player, np).await;
+        }
+
+        PlayerController::from(player).skip_current(&np).await?;
         ctx.say(format!("Skipped {}", np.info.title)).await?;
     } else {
         user_error!("nothing to skip!")
@@ -221,9 +502,13 @@ pub async fn skip(ctx: Context<'_>) -> Result<(), Error> {
 /// Pause the current song.
 #[poise::command(slash_command, prefix_command)]
 pub async fn pause(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+    let player = permissions::require_same_channel(ctx).await?;
 
     player.set_pause(true).await?;
+    // Whoever asked takes over from the watchdog
+    PlayerData::from(&player)
+        .auto_paused
+        .store(false, Ordering::SeqCst);
 
     ctx.say("Paused").await?;
 
@@ -233,60 +518,65 @@ pub async fn pause(ctx: Context<'_>) -> Result<(), Error> {
 /// Resume playing the current song.
 #[poise::command(slash_command, prefix_command)]
 pub async fn resume(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+    let player = permissions::require_same_channel(ctx).await?;
 
     player.set_pause(false).await?;
+    PlayerData::from(&player)
+        .auto_paused
+        .store(false, Ordering::SeqCst);
     ctx.say("Resumed playback").await?;
 
     Ok(())
 }
 
-/// Stops the playback of the current song.
+/// Change the playback volume.
 #[poise::command(slash_command, prefix_command)]
-pub async fn stop(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+pub async fn volume(
+    ctx: Context<'_>,
+    #[description = "Volume in percent, 100 is normal"]
+    #[min = 0]
+    #[max = 200]
+    volume: u16,
+) -> Result<(), Error> {
+    let player = permissions::require_same_channel(ctx).await?;
 
-    let now_playing = player.get_player().await?.track;
-    if let Some(np) = now_playing {
-        player.stop_now().await?;
-        ctx.say(format!("Stopped {}", np.info.title)).await?;
-    } else {
-        user_error!("nothing to stop!")
+    if volume > 200 {
+        user_error!("The volume can't be higher than 200%")
     }
 
+    player.set_volume(volume).await?;
+    ctx.say(format!("Volume set to {volume}%")).await?;
+
     Ok(())
 }
 
-/// Jump to a specific time in the song, in seconds.
+/// Stops the playback of the current song.
 #[poise::command(slash_command, prefix_command)]
-pub async fn seek(
-    ctx: Context<'_>,
-    #[description = "Time to jump to (in seconds)"] time: u64,
-) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+pub async fn stop(ctx: Context<'_>) -> Result<(), Error> {
+    let player = permissions::require_dj(ctx).await?;
 
     let now_playing = player.get_player().await?.track;
-    if now_playing.is_some() {
-        player.set_position(Duration::from_secs(time)).await?;
-        ctx.say(format!("Jumped to {}s", time)).await?;
+    if let Some(np) = now_playing {
+        player.stop_now().await?;
+        ctx.say(format!("Stopped {}", np.info.title)).await?;
     } else {
-        user_error!("nothing is playing!")
+        user_error!("nothing to stop!")
     }
 
     Ok(())
 }
 
-/// Remove a specific song from the queue.
-#[poise::command(slash_command, prefix_command)]
-pub async fn remove(
+/// Repeat the current track or the whole queue.
+#[poise::command(slash_command, prefix_command, rename = "loop")]
+pub async fn loop_(
     ctx: Context<'_>,
-    #[description = "Queue item index to remove"] index: usize,
+    #[description = "What to repeat"] mode: LoopMode,
 ) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
-
-    player.get_queue().remove(index)?;
+    let player = permissions::require_dj(ctx).await?;
+    let controller = PlayerController::from(player);
 
-    ctx.say("Removed successfully").await?;
+    controller.set_loop_mode(mode).await?;
+    ctx.say(format!("Loop mode: {}", mode.name())).await?;
 
     Ok(())
 }
@@ -294,19 +584,14 @@ pub async fn remove(
 /// Shuffles the queue.
 #[poise::command(slash_command, prefix_command)]
 pub async fn shuffle(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
+    let controller = PlayerController::from(permissions::require_dj(ctx).await?);
 
-    let mut queue = player
-        .get_queue()
-        .get_queue()
-        .await?
-        .make_contiguous()
-        .to_vec();
-    {
-        let mut rng = rand::rng();
-        queue.shuffle(&mut rng);
-    }
-    player.get_queue().replace(queue.into())?;
+    controller
+        .edit_user_queue(|queue| {
+            queue.make_contiguous().shuffle(&mut rand::rng());
+            Ok(())
+        })
+        .await?;
 
     ctx.say("Queue shuffled").await?;
 
@@ -316,9 +601,15 @@ pub async fn shuffle(ctx: Context<'_>) -> Result<(), Error> {
 /// Clear the current queue.
 #[poise::command(slash_command, prefix_command)]
 pub async fn clear(ctx: Context<'_>) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
-
-    player.get_queue().clear()?;
+    let controller = PlayerController::from(permissions::require_dj(ctx).await?);
+
+    // Keeps looping the current track, if that's what it's doing
+    controller
+        .edit_user_queue(|queue| {
+            queue.clear();
+            Ok(())
+        })
+        .await?;
 
     ctx.say("Queue cleared successfully").await?;
 
@@ -332,23 +623,57 @@ pub async fn swap(
     #[description = "Queue item index to swap"] index1: usize,
     #[description = "The other queue item index to swap"] index2: usize,
 ) -> Result<(), Error> {
-    let player = check_if_in_channel(ctx).await?;
-    let queue = player.get_queue();
-    let queue_len = queue.get_count().await?;
-
-    if index1 > queue_len || index2 > queue_len {
-        user_error!("Maximum allowed index: {}", queue_len)
-    } else if index1 == index2 {
-        user_error!("Can't swap between the same indexes")
-    }
+    let controller = PlayerController::from(permissions::require_dj(ctx).await?);
+    let message = controller
+        .edit_user_queue(|queue| {
+            let queue_len = queue.len();
+            if index1 == 0 || index2 == 0 || index1 > queue_len || index2 > queue_len {
+                user_error!("Queue positions go from 1 to {}", queue_len)
+            } else if index1 == index2 {
+                user_error!("Can't swap between the same indexes")
+            }
 
-    let track1 = queue.get_track(index1 - 1).await?.unwrap();
-    let track2 = queue.get_track(index2 - 1).await?.unwrap();
+            let (track1, track2) = (&queue[index1 - 1].track, &queue[index2 - 1].track);
+            let message = format!(
+                "Swapped {} - {} and {} - {}",
+                track1.info.author, track1.info.title, track2.info.author, track2.info.title
+            );
 
-    queue.swap(index1 - 1, track2)?;
-    queue.swap(index2 - 1, track1)?;
+            queue.swap(index1 - 1, index2 - 1);
+            Ok(message)
+        })
+        .await?;
 
-    ctx.say("Swapped successfully").await?;
+    ctx.say(message).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::commands::PlayOptions;
+
+    #[test]
+    fn strips_leading_play_options() {
+        let (options, term) =
+            PlayOptions::strip_from("--next --shuffle range:3-7 lofi beats").unwrap();
+        assert!(options.next && options.shuffle && !options.reverse);
+        assert_eq!(options.range.map(|r| (r.start, r.end)), Some((3, Some(7))));
+        assert_eq!(term, "lofi beats");
+
+        let (options, term) = PlayOptions::strip_from("--reverse range:5-").unwrap();
+        assert!(options.reverse);
+        assert_eq!(options.range.map(|r| (r.start, r.end)), Some((5, None)));
+        assert_eq!(term, "");
+    }
+
+    #[test]
+    fn keeps_searches_that_look_like_options() {
+        for search in ["next to you", "22 savage", "shuffle dance", "3-7 remix"] {
+            let (options, term) = PlayOptions::strip_from(search).unwrap();
+            assert!(!options.next && !options.shuffle && options.range.is_none());
+            assert_eq!(term, search);
+        }
+        assert!(PlayOptions::strip_from("range:abc lofi").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::*;
+use poise_error::anyhow::{bail, ensure};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The global configuration. Panics if called before [`init`].
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("config::init wasn't called")
+}
+
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("config::init was called twice")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub lavalink: LavalinkConfig,
+    pub player: PlayerConfig,
+    pub search: SearchConfig,
+    /// Directory for everything we persist
+    pub data_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            discord: Default::default(),
+            lavalink: Default::default(),
+            player: Default::default(),
+            search: Default::default(),
+            data_dir: PathBuf::from("data"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub token: String,
+    pub prefix: String,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            prefix: "!".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LavalinkConfig {
+    /// Players are moved to another node when theirs goes down
+    pub nodes: Vec<NodeConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// `host:port` of the node
+    pub hostname: String,
+    pub password: String,
+    pub ssl: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// How long the bot stays in a voice channel without listeners
+    pub alone_timeout_secs: u64,
+    /// Played when joining a channel: a URL, `file:<name>` for a bundled sound or one from `sounds_dir`, or empty to
+    /// disable.
+    pub join_sound: String,
+    /// Where additional `file:` sounds are. The bot serves them to Lavalink, see [`crate::sound_server`].
+    pub sounds_dir: PathBuf,
+    /// Where the bot serves `file:` sounds, or empty to not serve them
+    pub sound_server_listen: String,
+    /// How Lavalink reaches [`Self::sound_server_listen`]
+    pub sound_server_url: String,
+    /// Share of listeners (in percent) needed to vote-skip someone else's track
+    pub skip_vote_percent: u8,
+    /// Let requesters take turns instead of playing tracks in the order they were queued
+    pub fair_queue: bool,
+    /// Queue related tracks when the queue runs out
+    pub autoplay: bool,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            alone_timeout_secs: 15 * 60,
+            join_sound: "file:join.wav".into(),
+            sounds_dir: "sounds".into(),
+            sound_server_listen: "0.0.0.0:2334".into(),
+            sound_server_url: "http://localhost:2334".into(),
+            skip_vote_percent: 50,
+            fair_queue: false,
+            autoplay: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    pub cache_ttl_secs: u64,
+    /// Used by `/play`
+    pub default_engine: String,
+    /// Used by `/search` and when looking for alternatives to broken tracks
+    pub preferred_engines: Vec<String>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl_secs: 3 * 60 * 60,
+            default_engine: "youtube".into(),
+            preferred_engines: vec!["youtube".into(), "deezer".into(), "soundcloud".into()],
+        }
+    }
+}
+
+impl Config {
+    /// Reads `$CONFIG` (or `config.toml`, if it exists), applies environment overrides and validates the result.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var_os("CONFIG").map(PathBuf::from);
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None if std::fs::exists("config.toml").unwrap_or(false) => {
+                Self::from_file(Path::new("config.toml"))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        override_from_env("DISCORD_TOKEN", &mut self.discord.token)?;
+        override_from_env("DISCORD_PREFIX", &mut self.discord.prefix)?;
+        // The environment can only describe a single node, so it overrides the first one
+        if self.lavalink.nodes.is_empty() {
+            self.lavalink.nodes.push(NodeConfig::default());
+        }
+        let node = &mut self.lavalink.nodes[0];
+        override_from_env("LAVALINK_URL", &mut node.hostname)?;
+        override_from_env("LAVALINK_PASSWORD", &mut node.password)?;
+        override_parsed_from_env("LAVALINK_SSL", &mut node.ssl)?;
+        override_parsed_from_env("ALONE_TIMEOUT_SECS", &mut self.player.alone_timeout_secs)?;
+        override_from_env("JOIN_SOUND", &mut self.player.join_sound)?;
+        if let Some(dir) = env_var("SOUNDS_DIR")? {
+            self.player.sounds_dir = dir.into();
+        }
+        override_from_env("SOUND_SERVER_LISTEN", &mut self.player.sound_server_listen)?;
+        override_from_env("SOUND_SERVER_URL", &mut self.player.sound_server_url)?;
+        override_parsed_from_env("SKIP_VOTE_PERCENT", &mut self.player.skip_vote_percent)?;
+        override_parsed_from_env("FAIR_QUEUE", &mut self.player.fair_queue)?;
+        override_parsed_from_env("AUTOPLAY", &mut self.player.autoplay)?;
+        override_parsed_from_env("SEARCH_CACHE_TTL_SECS", &mut self.search.cache_ttl_secs)?;
+        override_from_env("DEFAULT_SEARCH_ENGINE", &mut self.search.default_engine)?;
+        if let Some(engines) = env_var("PREFERRED_SEARCH_ENGINES")? {
+            self.search.preferred_engines = engines.split(',').map(|s| s.trim().into()).collect();
+        }
+        if let Some(dir) = env_var("DATA_DIR")? {
+            self.data_dir = dir.into();
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.discord.token.is_empty(),
+            "discord.token is not set (or set $DISCORD_TOKEN / $DISCORD_TOKEN_FILE)"
+        );
+        ensure!(
+            !self.discord.prefix.is_empty(),
+            "discord.prefix must not be empty"
+        );
+        for (i, node) in self.lavalink.nodes.iter().enumerate() {
+            ensure!(
+                !node.hostname.is_empty(),
+                "lavalink.nodes[{i}].hostname is not set (or set $LAVALINK_URL for the first node)"
+            );
+            ensure!(
+                !node.password.is_empty(),
+                "lavalink.nodes[{i}].password is not set (or set $LAVALINK_PASSWORD / $LAVALINK_PASSWORD_FILE for the first node)"
+            );
+        }
+        ensure!(
+            self.player.alone_timeout_secs > 0,
+            "player.alone_timeout_secs must be greater than 0"
+        );
+        validate_join_sound(&self.player.join_sound)
+            .with_context(|| "Invalid player.join_sound")?;
+        ensure!(
+            self.player.sound_server_url.starts_with("http://")
+                || self.player.sound_server_url.starts_with("https://"),
+            "player.sound_server_url must be an http:// or https:// URL"
+        );
+        ensure!(
+            (1..=100).contains(&self.player.skip_vote_percent),
+            "player.skip_vote_percent must be between 1 and 100"
+        );
+        ensure!(
+            !self.search.preferred_engines.is_empty(),
+            "search.preferred_engines must not be empty"
+        );
+
+        parse_search_engine(&self.search.default_engine)
+            .with_context(|| "Invalid search.default_engine")?;
+        for engine in &self.search.preferred_engines {
+            parse_search_engine(engine).with_context(|| "Invalid search.preferred_engines")?;
+        }
+        Ok(())
+    }
+
+    pub fn search_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.search.cache_ttl_secs)
+    }
+
+    pub fn default_search_engine(&self) -> SearchEngines {
+        parse_search_engine(&self.search.default_engine).expect("validated on load")
+    }
+
+    pub fn preferred_search_engines(&self) -> Vec<SearchEngines> {
+        self.search
+            .preferred_engines
+            .iter()
+            .map(|e| parse_search_engine(e).expect("validated on load"))
+            .collect()
+    }
+
+    pub fn join_sound(&self) -> Option<&str> {
+        Some(self.player.join_sound.as_str()).filter(|s| !s.is_empty())
+    }
+
+    /// What Lavalink should load for a sound, resolving `file:<name>` to its URL on the sound server.
+    pub fn sound_identifier(&self, sound: &str) -> String {
+        match sound.strip_prefix("file:") {
+            Some(name) => format!(
+                "{}/{name}",
+                self.player.sound_server_url.trim_end_matches('/')
+            ),
+            None => sound.to_string(),
+        }
+    }
+}
+
+/// Whether `name` can be a `file:` sound. Kept to characters that are safe in both paths and URLs.
+pub fn is_sound_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        && name.chars().any(|c| c != '.')
+}
+
+/// Checks that a join sound is a URL, `file:<name>` or empty.
+pub fn validate_join_sound(sound: &str) -> Result<()> {
+    if sound.is_empty() || sound.starts_with("http://") || sound.starts_with("https://") {
+        return Ok(());
+    }
+    match sound.strip_prefix("file:") {
+        Some(name) if is_sound_name(name) => Ok(()),
+        Some(_) => bail!(
+            "file: needs a file name from the sounds directory, like file:join.wav, made of letters, digits, '.', '-' \
+            and '_'"
+        ),
+        None => bail!("The join sound must be a URL, file:<name> or nothing"),
+    }
+}
+
+pub fn parse_search_engine(name: &str) -> Result<SearchEngines> {
+    Ok(match name {
+        "youtube" => SearchEngines::YouTube,
+        "youtube_music" => SearchEngines::YouTubeMusic,
+        "soundcloud" => SearchEngines::SoundCloud,
+        "deezer" => SearchEngines::Deezer,
+        "spotify" => SearchEngines::Spotify,
+        "apple_music" => SearchEngines::AppleMusic,
+        "yandex_music" => SearchEngines::YandexMusic,
+        _ => bail!("Unknown search engine \"{name}\""),
+    })
+}
+
+/// Reads `$NAME`, or the contents of the file at `$NAME_FILE` (for Docker secrets).
+fn env_var(name: &str) -> Result<Option<String>> {
+    if let Ok(value) = std::env::var(name) {
+        return Ok(Some(value));
+    }
+
+    let Ok(path) = std::env::var(format!("{name}_FILE")) else {
+        return Ok(None);
+    };
+    let value = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read ${name}_FILE ({path})"))?;
+    Ok(Some(value.trim_end().to_string()))
+}
+
+fn override_from_env(name: &str, target: &mut String) -> Result<()> {
+    if let Some(value) = env_var(name)? {
+        *target = value;
+    }
+    Ok(())
+}
+
+fn override_parsed_from_env<T>(name: &str, target: &mut T) -> Result<()>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    if let Some(value) = env_var(name)? {
+        *target = value
+            .parse()
+            .with_context(|| format!("Invalid value for ${name}: \"{value}\""))?;
+    }
+    Ok(())
+}
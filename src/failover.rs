@@ -0,0 +1,119 @@
+use crate::player_controller::PlayerController;
+use crate::*;
+use arc_swap::ArcSwapOption;
+use lavalink_rs::node::Node;
+use std::sync::atomic::Ordering;
+
+/// Watches the Lavalink nodes and moves players off nodes that went down.
+pub async fn monitor_nodes(lavalink: LavalinkClient) {
+    let mut was_running: Vec<bool> = lavalink.nodes.iter().map(|n| is_running(n)).collect();
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        for (node, was_running) in lavalink.nodes.iter().zip(&mut was_running) {
+            let running = is_running(node);
+            if *was_running && !running {
+                warn!("Lavalink node {} went down, moving its players", node.id);
+                migrate_players(&lavalink, node.id).await;
+            }
+            *was_running = running;
+        }
+    }
+}
+
+/// Drops the player contexts we still hold for a node. Used when it (re)connects with a fresh session, which means
+/// Lavalink has already forgotten about them.
+pub fn forget_node_players(lavalink: &LavalinkClient, node_id: usize) {
+    for guild_id in guilds_on_node(lavalink, node_id) {
+        forget_player(lavalink, guild_id);
+    }
+}
+
+/// Drops our side of a player without asking its node to delete it.
+pub fn forget_player(lavalink: &LavalinkClient, guild_id: GuildId) {
+    if let Some((_, (player_ctx, _))) = lavalink.players.remove(&guild_id) {
+        if let Some(player_ctx) = player_ctx.load_full() {
+            let _ = (*player_ctx).clone().close();
+        }
+    }
+}
+
+fn is_running(node: &Node) -> bool {
+    node.is_running.load(Ordering::SeqCst)
+}
+
+/// The running node with the fewest players.
+fn running_node(lavalink: &LavalinkClient) -> Option<Arc<Node>> {
+    lavalink
+        .nodes
+        .iter()
+        .filter(|node| is_running(node))
+        .min_by_key(|node| guilds_on_node(lavalink, node.id).len())
+        .cloned()
+}
+
+fn guilds_on_node(lavalink: &LavalinkClient, node_id: usize) -> Vec<GuildId> {
+    lavalink
+        .players
+        .iter()
+        .filter(|entry| entry.value().1.id == node_id)
+        .map(|entry| *entry.key())
+        .collect()
+}
+
+async fn migrate_players(lavalink: &LavalinkClient, node_id: usize) {
+    for guild_id in guilds_on_node(lavalink, node_id) {
+        let Some(player_ctx) = lavalink.get_player_context(guild_id) else {
+            continue;
+        };
+        let controller = PlayerController::from(player_ctx);
+
+        let notice = match migrate_player(lavalink, &controller).await {
+            Ok(()) => "Lost the connection to the audio server, continuing on another one.",
+            Err(e) => {
+                error!("Failed to move player for guild {}: {e:#}", guild_id.0);
+                "Lost the connection to the audio server and couldn't find another one :("
+            }
+        };
+
+        let data = &controller.data;
+        if let Err(e) = data.text_channel.say(&data.http, notice).await {
+            error!("Failed to post failover notice: {e:#}");
+        }
+    }
+}
+
+async fn migrate_player(lavalink: &LavalinkClient, controller: &PlayerController) -> Result<()> {
+    let guild_id = controller.data.guild_id;
+
+    // The player context keeps its last known state and the queue locally, so they survive the node
+    let player = controller.ctx.get_player().await?;
+    // The loop copy is queued again when the current track restarts
+    let (queue, _) = controller.user_queue().await?;
+
+    let node = running_node(lavalink).with_context(|| "No Lavalink node is running")?;
+    forget_player(lavalink, guild_id);
+    // New players go to the node the guild is assigned to. Otherwise the distribution strategy picks one, which might
+    // be the node that just went down.
+    lavalink
+        .players
+        .insert(guild_id, (ArcSwapOption::empty(), node));
+
+    let connection_info = lavalink
+        .get_connection_info(guild_id, Duration::from_secs(3))
+        .await?;
+    let player_ctx = lavalink
+        .create_player_context_with_data(guild_id, connection_info, controller.data.clone())
+        .await?;
+
+    player_ctx.set_volume(player.volume).await?;
+    PlayerController::from(player_ctx)
+        .restore_playback(
+            queue,
+            player.track.clone(),
+            seek::current_position(&player),
+            player.paused,
+        )
+        .await
+}
@@ -0,0 +1,225 @@
+use crate::player_controller::PlayerController;
+use crate::util::{TrackUserData, is_autoplay};
+use crate::*;
+use lavalink_rs::prelude::TrackInQueue;
+use std::collections::{HashMap, VecDeque};
+
+/// Orders `items` so their requesters take turns, each keeping their own order. Requesters go in order of their first
+/// item, except `last_requester` (whose track just played) waits until the others had a turn.
+///
+/// Items without a requester (e.g. the join announcement) stay in front.
+pub fn fair_order<T>(
+    items: impl IntoIterator<Item = T>,
+    requester: impl Fn(&T) -> Option<u64>,
+    last_requester: Option<u64>,
+) -> Vec<T> {
+    let mut ordered = vec![];
+    let mut turns: Vec<u64> = vec![];
+    let mut by_requester: HashMap<u64, VecDeque<T>> = HashMap::new();
+
+    for item in items {
+        match requester(&item) {
+            Some(id) => {
+                if !by_requester.contains_key(&id) {
+                    turns.push(id);
+                }
+                by_requester.entry(id).or_default().push_back(item);
+            }
+            None => ordered.push(item),
+        }
+    }
+
+    if let Some(last) = last_requester {
+        if let Some(i) = turns.iter().position(|&id| id == last) {
+            let id = turns.remove(i);
+            turns.push(id);
+        }
+    }
+
+    while !by_requester.is_empty() {
+        for id in &turns {
+            let Some(items) = by_requester.get_mut(id) else {
+                continue;
+            };
+            ordered.extend(items.pop_front());
+            if items.is_empty() {
+                by_requester.remove(id);
+            }
+        }
+    }
+
+    ordered
+}
+
+/// Where to insert an item by `requester` into `queue`, given as the requester of each item, so requesters take turns
+/// like with [`fair_order`]. Nothing queued already moves, so tracks users placed explicitly stay where they are.
+pub fn fair_insert_index(
+    queue: &[Option<u64>],
+    requester: u64,
+    last_requester: Option<u64>,
+) -> usize {
+    // The requester whose track is playing already had this round's turn
+    let had_turn = |id: u64| usize::from(last_requester == Some(id));
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    let rounds: Vec<usize> = queue
+        .iter()
+        .map(|item| match item {
+            Some(id) => {
+                let count = counts.entry(*id).or_default();
+                *count += 1;
+                *count - 1 + had_turn(*id)
+            }
+            None => 0,
+        })
+        .collect();
+
+    let round = counts.get(&requester).copied().unwrap_or_default() + had_turn(requester);
+    rounds
+        .iter()
+        .rposition(|&r| r <= round)
+        .map_or(0, |i| i + 1)
+}
+
+fn requester_of(track: &TrackInQueue) -> Option<u64> {
+    TrackUserData::try_from(&track.track)
+        .ok()
+        .map(|data| data.requester_id.0)
+}
+
+/// Inserts `tracks` by `requester` into `queue` at the positions from [`fair_insert_index`].
+pub fn insert_fairly(
+    queue: &mut VecDeque<TrackInQueue>,
+    tracks: impl IntoIterator<Item = TrackInQueue>,
+    requester: u64,
+    last_requester: Option<u64>,
+) {
+    let mut requesters: Vec<_> = queue.iter().map(requester_of).collect();
+    for track in tracks {
+        let index = fair_insert_index(&requesters, requester, last_requester);
+        requesters.insert(index, Some(requester));
+        queue.insert(index, track);
+    }
+}
+
+impl PlayerController {
+    /// Who requested the current track, who [`fair_order`] lets wait. Autoplay tracks don't use up anyone's turn.
+    pub async fn last_requester(&self) -> Result<Option<u64>> {
+        Ok(self
+            .ctx
+            .get_player()
+            .await?
+            .track
+            .and_then(|t| TrackUserData::try_from(&t).ok())
+            .filter(|data| !data.autoplay)
+            .map(|data| data.requester_id.0))
+    }
+
+    /// Reorders the whole queue with [`fair_order`], keeping autoplay tracks at the end.
+    pub async fn apply_fair_order(&self) -> Result<()> {
+        let last_requester = self.last_requester().await?;
+        self.edit_user_queue(|queue| {
+            let (autoplay, requested): (Vec<_>, Vec<_>) = std::mem::take(queue)
+                .into_iter()
+                .partition(|t| is_autoplay(&t.track));
+            *queue = fair_order(requested, requester_of, last_requester)
+                .into_iter()
+                .chain(autoplay)
+                .collect();
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fair_queue::{fair_insert_index, fair_order};
+
+    fn order(items: &[(Option<u64>, &'static str)], last: Option<u64>) -> Vec<&'static str> {
+        fair_order(items.iter().copied(), |(requester, _)| *requester, last)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    fn insert_all(
+        queue: &[(Option<u64>, &'static str)],
+        items: &[(u64, &'static str)],
+        last: Option<u64>,
+    ) -> Vec<&'static str> {
+        let mut queue = queue.to_vec();
+        for &(requester, name) in items {
+            let requesters: Vec<_> = queue.iter().map(|(r, _)| *r).collect();
+            let index = fair_insert_index(&requesters, requester, last);
+            queue.insert(index, (Some(requester), name));
+        }
+        queue.into_iter().map(|(_, name)| name).collect()
+    }
+
+    #[test]
+    fn requesters_take_turns() {
+        let items = [
+            (Some(1), "a1"),
+            (Some(1), "a2"),
+            (Some(1), "a3"),
+            (Some(2), "b1"),
+            (Some(3), "c1"),
+            (Some(2), "b2"),
+        ];
+        assert_eq!(order(&items, None), ["a1", "b1", "c1", "a2", "b2", "a3"]);
+    }
+
+    #[test]
+    fn last_requester_waits() {
+        let items = [(Some(1), "a1"), (Some(2), "b1"), (Some(1), "a2")];
+        assert_eq!(order(&items, Some(1)), ["b1", "a1", "a2"]);
+    }
+
+    #[test]
+    fn is_stable_and_keeps_unrequested_in_front() {
+        let items = [
+            (Some(1), "a1"),
+            (None, "join"),
+            (Some(2), "b1"),
+            (Some(1), "a2"),
+        ];
+        let ordered = order(&items, None);
+        assert_eq!(ordered, ["join", "a1", "b1", "a2"]);
+
+        let reordered: Vec<_> = ordered
+            .iter()
+            .map(|&name| *items.iter().find(|(_, n)| *n == name).unwrap())
+            .collect();
+        assert_eq!(order(&reordered, None), ordered);
+    }
+
+    #[test]
+    fn inserting_matches_fair_order() {
+        let items = [
+            (1, "a1"),
+            (1, "a2"),
+            (1, "a3"),
+            (2, "b1"),
+            (3, "c1"),
+            (2, "b2"),
+        ];
+        assert_eq!(
+            insert_all(&[], &items, None),
+            ["a1", "b1", "c1", "a2", "b2", "a3"]
+        );
+        assert_eq!(
+            insert_all(&[(None, "join")], &[(1, "a1"), (2, "b1")], Some(1)),
+            ["join", "b1", "a1"]
+        );
+    }
+
+    #[test]
+    fn inserting_keeps_queued_order() {
+        // b1 was moved to the front and a2 queued to play next
+        let queue = [(Some(2), "b1"), (Some(1), "a2"), (Some(1), "a1")];
+        assert_eq!(
+            insert_all(&queue, &[(2, "b2"), (3, "c1")], None),
+            ["b1", "a2", "c1", "a1", "b2"]
+        );
+    }
+}
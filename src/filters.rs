@@ -0,0 +1,256 @@
+use crate::player_controller::PlayerController;
+use crate::*;
+use itertools::Itertools;
+use lavalink_rs::model::player::{Equalizer, Filters, Karaoke, Rotation, Timescale};
+use poise::ChoiceParameter;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, poise::ChoiceParameter)]
+pub enum FilterPreset {
+    #[name = "bassboost"]
+    BassBoost,
+    #[name = "nightcore"]
+    Nightcore,
+    #[name = "vaporwave"]
+    Vaporwave,
+    #[name = "karaoke"]
+    Karaoke,
+    #[name = "rotation"]
+    Rotation,
+}
+
+impl FilterPreset {
+    fn apply(self, filters: &mut Filters, bands: &mut BTreeMap<u8, f64>) {
+        match self {
+            FilterPreset::BassBoost => {
+                bands.extend([(0, 0.2), (1, 0.15), (2, 0.1), (3, 0.05)]);
+            }
+            FilterPreset::Nightcore => {
+                filters.timescale = Some(Timescale {
+                    speed: Some(1.25),
+                    pitch: Some(1.25),
+                    rate: Some(1.0),
+                })
+            }
+            FilterPreset::Vaporwave => {
+                filters.timescale = Some(Timescale {
+                    speed: Some(0.85),
+                    pitch: Some(0.8),
+                    rate: Some(1.0),
+                });
+                bands.extend([(0, 0.3), (1, 0.3)]);
+            }
+            FilterPreset::Karaoke => {
+                filters.karaoke = Some(Karaoke {
+                    level: Some(1.0),
+                    mono_level: Some(1.0),
+                    filter_band: Some(220.0),
+                    filter_width: Some(100.0),
+                })
+            }
+            FilterPreset::Rotation => {
+                filters.rotation = Some(Rotation {
+                    rotation_hz: Some(0.2),
+                })
+            }
+        }
+    }
+}
+
+/// The filters a player was asked to use. Presets are applied in order, manual settings win over presets.
+#[derive(Default, Clone)]
+pub struct ActiveFilters {
+    pub presets: Vec<FilterPreset>,
+    /// Gain per equalizer band (0-14)
+    pub bands: BTreeMap<u8, f64>,
+    pub timescale: Option<Timescale>,
+}
+
+impl ActiveFilters {
+    pub fn is_empty(&self) -> bool {
+        self.presets.is_empty() && self.bands.is_empty() && self.timescale.is_none()
+    }
+
+    /// Returns whether the preset is now active.
+    pub fn toggle(&mut self, preset: FilterPreset) -> bool {
+        if let Some(i) = self.presets.iter().position(|p| *p == preset) {
+            self.presets.remove(i);
+            false
+        } else {
+            self.presets.push(preset);
+            true
+        }
+    }
+
+    pub fn to_filters(&self) -> Filters {
+        let mut filters = Filters::default();
+        let mut bands = BTreeMap::new();
+        for preset in &self.presets {
+            preset.apply(&mut filters, &mut bands);
+        }
+
+        bands.extend(&self.bands);
+        if !bands.is_empty() {
+            let equalizer = bands
+                .into_iter()
+                .map(|(band, gain)| Equalizer { band, gain })
+                .collect();
+            filters.equalizer = Some(equalizer);
+        }
+        if let Some(timescale) = &self.timescale {
+            filters.timescale = Some(timescale.clone());
+        }
+
+        filters
+    }
+
+    /// Human-readable summary, one entry per active filter.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.presets.iter().map(|p| p.name().into()).collect();
+        if !self.bands.is_empty() {
+            let bands = self
+                .bands
+                .iter()
+                .map(|(band, gain)| format!("{band}: {gain:+.2}"))
+                .join(", ");
+            lines.push(format!("equalizer ({bands})"));
+        }
+        if let Some(t) = &self.timescale {
+            lines.push(format!(
+                "timescale (speed {:.2}, pitch {:.2}, rate {:.2})",
+                t.speed.unwrap_or(1.0),
+                t.pitch.unwrap_or(1.0),
+                t.rate.unwrap_or(1.0)
+            ));
+        }
+        lines
+    }
+}
+
+impl PlayerController {
+    /// Changes the active filters and sends the result to Lavalink.
+    pub async fn update_filters(
+        &self,
+        f: impl FnOnce(&mut ActiveFilters),
+    ) -> Result<ActiveFilters> {
+        let active = {
+            let mut guard = self.data.filters.lock();
+            f(&mut guard);
+            guard.clone()
+        };
+        self.ctx.set_filters(active.to_filters()).await?;
+        Ok(active)
+    }
+}
+
+fn describe_reply(active: &ActiveFilters) -> String {
+    if active.is_empty() {
+        "No filters active".into()
+    } else {
+        format!("Active filters: {}", active.describe().join(", "))
+    }
+}
+
+/// Change how playback sounds.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("preset", "equalizer", "timescale", "reset"),
+    subcommand_required
+)]
+pub async fn filter(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Toggle a filter preset. Rotation is also known as 8D audio.
+#[poise::command(slash_command, prefix_command)]
+async fn preset(
+    ctx: Context<'_>,
+    #[description = "The preset to toggle"] preset: FilterPreset,
+) -> Result<(), Error> {
+    let controller = PlayerController::from(permissions::require_same_channel(ctx).await?);
+
+    let mut enabled = false;
+    let active = controller
+        .update_filters(|f| enabled = f.toggle(preset))
+        .await?;
+
+    let verb = if enabled { "Enabled" } else { "Disabled" };
+    ctx.say(format!(
+        "{verb} {}. {}",
+        preset.name(),
+        describe_reply(&active)
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Set the gain of a single equalizer band.
+#[poise::command(slash_command, prefix_command)]
+async fn equalizer(
+    ctx: Context<'_>,
+    #[description = "Band from 0 (25 Hz) to 14 (16 kHz)"]
+    #[min = 0]
+    #[max = 14]
+    band: u8,
+    #[description = "Gain from -0.25 (muted) to 1.0 (doubled), 0 to reset"] gain: f64,
+) -> Result<(), Error> {
+    if band > 14 {
+        user_error!("The band must be between 0 and 14")
+    }
+    if !(-0.25..=1.0).contains(&gain) {
+        user_error!("The gain must be between -0.25 and 1.0")
+    }
+
+    let controller = PlayerController::from(permissions::require_same_channel(ctx).await?);
+    let active = controller
+        .update_filters(|f| {
+            if gain == 0.0 {
+                f.bands.remove(&band);
+            } else {
+                f.bands.insert(band, gain);
+            }
+        })
+        .await?;
+
+    ctx.say(describe_reply(&active)).await?;
+    Ok(())
+}
+
+/// Change playback speed and pitch.
+#[poise::command(slash_command, prefix_command)]
+async fn timescale(
+    ctx: Context<'_>,
+    #[description = "Playback speed, 1.0 is normal"] speed: Option<f64>,
+    #[description = "Pitch, 1.0 is normal"] pitch: Option<f64>,
+    #[description = "Speed and pitch combined, 1.0 is normal"] rate: Option<f64>,
+) -> Result<(), Error> {
+    for value in [speed, pitch, rate].into_iter().flatten() {
+        if !(0.25..=3.0).contains(&value) {
+            user_error!("Values must be between 0.25 and 3.0")
+        }
+    }
+
+    let controller = PlayerController::from(permissions::require_same_channel(ctx).await?);
+    let active = controller
+        .update_filters(|f| {
+            f.timescale = (speed.is_some() || pitch.is_some() || rate.is_some())
+                .then_some(Timescale { speed, pitch, rate })
+        })
+        .await?;
+
+    ctx.say(describe_reply(&active)).await?;
+    Ok(())
+}
+
+/// Turn off all filters.
+#[poise::command(slash_command, prefix_command)]
+async fn reset(ctx: Context<'_>) -> Result<(), Error> {
+    let controller = PlayerController::from(permissions::require_same_channel(ctx).await?);
+    controller
+        .update_filters(|f| *f = ActiveFilters::default())
+        .await?;
+
+    ctx.say("Filters turned off").await?;
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use crate::player_controller::PlayerController;
+use crate::util::TrackUserData;
+use crate::*;
+use chrono::{DateTime, Utc};
+use lavalink_rs::model::events::TrackEndReason;
+use lavalink_rs::model::track::TrackData;
+use parking_lot::Mutex;
+use poise::serenity_prelude::CreateEmbed;
+use std::collections::{HashMap, VecDeque};
+use std::sync::LazyLock;
+
+/// How many tracks to remember per guild
+const HISTORY_LENGTH: usize = 50;
+
+static HISTORY: LazyLock<Mutex<HashMap<GuildId, VecDeque<HistoryEntry>>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrackEnding {
+    Finished,
+    Skipped,
+    Stopped,
+    Failed,
+    ReplacedByAlternative,
+    /// Made way for `/playnow`, and continues afterwards
+    Interrupted,
+}
+
+impl TrackEnding {
+    pub fn describe(self) -> &'static str {
+        match self {
+            TrackEnding::Finished => "finished",
+            TrackEnding::Skipped => "⏭️ skipped",
+            TrackEnding::Stopped => "⏹️ stopped",
+            TrackEnding::Failed => "⚠️ failed",
+            TrackEnding::ReplacedByAlternative => "⚠️ replaced by an alternative",
+            TrackEnding::Interrupted => "⏯️ interrupted",
+        }
+    }
+}
+
+impl From<&TrackEndReason> for TrackEnding {
+    fn from(reason: &TrackEndReason) -> Self {
+        match reason {
+            TrackEndReason::Finished => TrackEnding::Finished,
+            TrackEndReason::LoadFailed => TrackEnding::Failed,
+            TrackEndReason::Replaced => TrackEnding::Skipped,
+            TrackEndReason::Stopped | TrackEndReason::Cleanup => TrackEnding::Stopped,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HistoryEntry {
+    pub track: TrackData,
+    pub requester_id: UserId,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the track is playing
+    pub ending: Option<TrackEnding>,
+}
+
+/// Records a track that just started. Tracks nobody requested (e.g. the join announcement) are ignored.
+pub fn record_start(guild_id: GuildId, track: &TrackData) {
+    let Ok(user_data) = TrackUserData::try_from(track) else {
+        return;
+    };
+
+    let mut history = HISTORY.lock();
+    let entries = history.entry(guild_id).or_default();
+    if entries.len() >= HISTORY_LENGTH {
+        entries.pop_back();
+    }
+    entries.push_front(HistoryEntry {
+        track: track.clone(),
+        requester_id: user_data.requester_id,
+        started_at: Utc::now(),
+        ending: None,
+    });
+}
+
+/// Notes how the latest entry for `track` ended, unless that was already recorded.
+pub fn record_end(guild_id: GuildId, track: &TrackData, ending: TrackEnding) {
+    let mut history = HISTORY.lock();
+    let entry = history.get_mut(&guild_id).and_then(|entries| {
+        entries
+            .iter_mut()
+            .find(|e| e.track.encoded == track.encoded)
+    });
+    if let Some(entry) = entry.filter(|e| e.ending.is_none()) {
+        entry.ending = Some(ending);
+    }
+}
+
+/// Most recent first.
+pub fn entries(guild_id: GuildId) -> Vec<HistoryEntry> {
+    HISTORY
+        .lock()
+        .get(&guild_id)
+        .map(|entries| entries.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn history_line(entry: &HistoryEntry) -> String {
+    let info = &entry.track.info;
+    let title = match &info.uri {
+        Some(uri) => format!("[{} - {}](<{}>)", info.author, info.title, uri),
+        None => format!("{} - {}", info.author, info.title),
+    };
+    let ending = entry.ending.map_or("▶️ playing", TrackEnding::describe);
+
+    format!(
+        "<t:{}:R> {title} | <@!{}> | {ending}",
+        entry.started_at.timestamp(),
+        entry.requester_id.0
+    )
+}
+
+/// Show recently played tracks.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    let entries = entries(ctx.guild_id().unwrap().into());
+    if entries.is_empty() {
+        user_error!("Nothing was played yet")
+    }
+
+    let lines: Vec<_> = entries.iter().map(history_line).collect();
+    let pages = lines
+        .chunks(10)
+        .map(|chunk| {
+            CreateEmbed::new()
+                .title("History")
+                .description(chunk.join("\n"))
+        })
+        .collect();
+    pagination::paginate(ctx, pages, 0).await?;
+
+    Ok(())
+}
+
+/// Put the previous track back at the front of the queue.
+#[poise::command(slash_command, prefix_command)]
+pub async fn previous(ctx: Context<'_>) -> Result<(), Error> {
+    let player = permissions::require_same_channel(ctx).await?;
+    let controller = PlayerController::from(player);
+
+    let Some(entry) = entries(controller.data.guild_id)
+        .into_iter()
+        .find(|e| e.ending.is_some())
+    else {
+        user_error!("There's no previous track")
+    };
+
+    controller
+        .edit_user_queue(|queue| {
+            queue.push_front(entry.track.clone().into());
+            Ok(())
+        })
+        .await?;
+    if controller.ctx.get_player().await?.track.is_none() {
+        controller.ctx.skip()?;
+    }
+
+    ctx.say(format!(
+        "Playing {} - {} next",
+        entry.track.info.author, entry.track.info.title
+    ))
+    .await?;
+
+    Ok(())
+}
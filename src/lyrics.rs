@@ -0,0 +1,420 @@
+use crate::player_controller::PlayerController;
+use crate::title_parse::guess_search_query;
+use crate::util::{check_if_in_channel, source_to_color};
+use crate::*;
+use itertools::Itertools;
+use lavalink_rs::http::Http;
+use lavalink_rs::model::track::TrackData;
+use lavalink_rs::node::Node;
+use poise::serenity_prelude::{CreateEmbed, CreateEmbedAuthor};
+use serde::Deserialize;
+
+/// Discord allows 4096 characters per embed description, but that's a lot of scrolling
+const PAGE_LENGTH: usize = 1500;
+
+/// Lyrics as returned by the LavaLyrics plugin
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Lyrics {
+    pub source_name: String,
+    pub provider: Option<String>,
+    pub text: Option<String>,
+    /// Empty unless the provider has synced lyrics
+    #[serde(default)]
+    pub lines: Vec<LyricsLine>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LyricsLine {
+    /// Start of the line, in milliseconds
+    pub timestamp: u64,
+    pub line: String,
+}
+
+impl Lyrics {
+    /// Index of the line being sung at `position`, if the lyrics are synced.
+    pub fn current_line(&self, position: u64) -> Option<usize> {
+        self.lines
+            .iter()
+            .rposition(|l| l.timestamp <= position)
+            .or_else(|| (!self.lines.is_empty()).then_some(0))
+    }
+
+    /// Splits the lyrics into pages, highlighting the line at `position` if they are synced. Also returns the
+    /// page with the highlighted line.
+    pub fn pages(&self, position: Option<u64>) -> (Vec<String>, usize) {
+        let current = position.and_then(|p| self.current_line(p));
+        let lines: Vec<String> = if self.lines.is_empty() {
+            let text = self.text.as_deref().unwrap_or_default();
+            text.lines().map(str::to_string).collect()
+        } else {
+            self.lines
+                .iter()
+                .enumerate()
+                .map(|(i, l)| {
+                    if Some(i) == current && !l.line.trim().is_empty() {
+                        format!("**▶ {}**", l.line.trim())
+                    } else {
+                        l.line.clone()
+                    }
+                })
+                .collect()
+        };
+
+        let mut pages = vec![];
+        let mut page = String::new();
+        let mut current_page = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if !page.is_empty() && page.len() + line.len() >= PAGE_LENGTH {
+                pages.push(std::mem::take(&mut page));
+            }
+            if Some(i) == current {
+                current_page = pages.len();
+            }
+            page.push_str(line);
+            page.push('\n');
+        }
+        if !page.trim().is_empty() || pages.is_empty() {
+            pages.push(page);
+        }
+
+        (pages, current_page)
+    }
+}
+
+impl PlayerController {
+    async fn node(&self) -> Arc<Node> {
+        self.data
+            .lavalink
+            .get_node_for_guild(self.data.guild_id)
+            .await
+    }
+
+    /// Lyrics for the track that's playing right now.
+    pub async fn current_lyrics(&self) -> Result<Option<Lyrics>> {
+        let node = self.node().await;
+        let path = current_lyrics_path(&node.session_id.load(), self.data.guild_id);
+        request_lyrics(&node.http, &path).await
+    }
+
+    pub async fn lyrics_for(&self, track: &TrackData) -> Result<Option<Lyrics>> {
+        let path = format!("/lyrics?track={}", percent_encode(&track.encoded));
+        request_lyrics(&self.node().await.http, &path).await
+    }
+
+    /// Looks for lyrics of a track that is similar to `track`, using the same guesses as for alternative tracks. A
+    /// search or lookup that fails just moves on to the next one.
+    pub async fn find_similar_lyrics(
+        &self,
+        track: &TrackData,
+    ) -> Result<Option<(TrackData, Lyrics)>> {
+        let info = &track.info;
+        let guesses = guess_search_query(&info.author, &info.title, info.length as usize).guesses;
+        let engines = settings::get(self.data.guild_id)
+            .await
+            .preferred_search_engines();
+
+        for guess in guesses.iter().take(3) {
+            let query = format!("{} {}", guess.components.0, guess.components.1);
+            for engine in &engines {
+                let candidate = match self.search_single(&query, engine).await {
+                    Ok(results) => results.into_iter().next(),
+                    Err(e) => {
+                        warn!("Failed to search for lyrics of \"{query}\": {e:#}");
+                        continue;
+                    }
+                };
+                let Some(candidate) = candidate else {
+                    continue;
+                };
+                match self.lyrics_for(&candidate).await {
+                    Ok(Some(lyrics)) => return Ok(Some((candidate, lyrics))),
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to get lyrics for \"{query}\": {e:#}"),
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn current_lyrics_path(session_id: &str, guild_id: GuildId) -> String {
+    format!("/sessions/{session_id}/players/{}/track/lyrics", guild_id.0)
+}
+
+/// Asks the LavaLyrics plugin for the lyrics at `path`.
+async fn request_lyrics(client: &Http, path: &str) -> Result<Option<Lyrics>> {
+    let uri = client.path_to_uri(path, true)?;
+    let body = client
+        .raw_request(http::Method::GET, uri, None::<&()>)
+        .await?;
+    parse_lyrics_response(&body)
+}
+
+fn parse_lyrics_response(body: &[u8]) -> Result<Option<Lyrics>> {
+    // The plugin answers with "204 No Content" if it didn't find anything
+    if body.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice(body)
+        .map(Some)
+        .with_context(|| "Failed to deserialize lyrics, is the LavaLyrics plugin installed?")
+}
+
+/// Percent-encodes everything but unreserved characters, for use in a query string.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .join("")
+}
+
+/// Show lyrics for the current track, or for a search query.
+#[poise::command(slash_command, prefix_command)]
+pub async fn lyrics(
+    ctx: Context<'_>,
+    #[description = "Search term or URL, defaults to the current track"]
+    #[rest]
+    query: Option<String>,
+) -> Result<(), Error> {
+    let player = check_if_in_channel(ctx).await?;
+    let controller = PlayerController::from(player);
+    ctx.defer().await?;
+
+    let (track, position, lyrics) = match query {
+        Some(query) => {
+            let track = match controller.load_or_search(&query).await? {
+                TrackLoadData::Track(t) => Some(t),
+                TrackLoadData::Search(results) => results.into_iter().next(),
+                TrackLoadData::Playlist(p) => p.tracks.into_iter().next(),
+                TrackLoadData::Error(_) => None,
+            };
+            let Some(track) = track else {
+                user_error!("No track found for \"{query}\"")
+            };
+            let lyrics = controller.lyrics_for(&track).await?;
+            (track, None, lyrics)
+        }
+        None => {
+            let player = controller.ctx.get_player().await?;
+            let Some(track) = player.track else {
+                user_error!("Nothing is playing, try with a search term")
+            };
+            let lyrics = controller.current_lyrics().await?;
+            (track, Some(player.state.position), lyrics)
+        }
+    };
+
+    let (track, lyrics) = match lyrics {
+        Some(lyrics) => (track, lyrics),
+        None => match controller.find_similar_lyrics(&track).await? {
+            // The position only makes sense for the track that is playing
+            Some(found) if position.is_none() => found,
+            Some((_, lyrics)) => (track, lyrics),
+            None => user_error!(
+                "No lyrics found for {} - {}",
+                track.info.author,
+                track.info.title
+            ),
+        },
+    };
+
+    let (pages, current_page) = lyrics.pages(position);
+    let provider = match &lyrics.provider {
+        Some(provider) => format!("Lyrics by {provider} via {}", lyrics.source_name),
+        None => format!("Lyrics via {}", lyrics.source_name),
+    };
+    let embeds = pages
+        .into_iter()
+        .map(|page| {
+            CreateEmbed::new()
+                .author(CreateEmbedAuthor::new(&provider))
+                .title(format!("{} - {}", track.info.author, track.info.title))
+                .description(page)
+                .color(source_to_color(&track.info.source_name))
+        })
+        .collect();
+    pagination::paginate(ctx, embeds, current_page).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lyrics::{
+        Lyrics, current_lyrics_path, parse_lyrics_response, percent_encode, request_lyrics,
+    };
+    use crate::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    const SYNCED: &str = r#"{
+        "sourceName": "spotify",
+        "provider": "MusixMatch",
+        "text": null,
+        "lines": [
+            { "timestamp": 0, "duration": 1000, "line": "first", "plugin": {} },
+            { "timestamp": 1000, "duration": 1000, "line": "second", "plugin": {} },
+            { "timestamp": 2000, "duration": 1000, "line": "third", "plugin": {} }
+        ],
+        "plugin": {}
+    }"#;
+
+    #[test]
+    fn highlights_current_line() {
+        let lyrics: Lyrics = serde_json::from_str(SYNCED).unwrap();
+        assert_eq!(lyrics.current_line(1500), Some(1));
+        assert_eq!(lyrics.current_line(99_999), Some(2));
+
+        let (pages, current_page) = lyrics.pages(Some(1500));
+        assert_eq!(pages, vec!["first\n**▶ second**\nthird\n"]);
+        assert_eq!(current_page, 0);
+    }
+
+    #[test]
+    fn parses_responses() {
+        let lyrics = parse_lyrics_response(SYNCED.as_bytes()).unwrap().unwrap();
+        assert_eq!(lyrics.provider.as_deref(), Some("MusixMatch"));
+        assert_eq!(lyrics.lines.len(), 3);
+
+        // No Content
+        assert!(parse_lyrics_response(b"").unwrap().is_none());
+    }
+
+    #[test]
+    fn fails_on_error_responses() {
+        // What Lavalink answers without the plugin
+        let not_found = br#"{
+            "timestamp": 1700000000000,
+            "status": 404,
+            "error": "Not Found",
+            "path": "/v4/lyrics"
+        }"#;
+        let error = parse_lyrics_response(not_found).unwrap_err();
+        assert!(format!("{error:#}").contains("LavaLyrics plugin"));
+
+        assert!(parse_lyrics_response(b"<html>Bad Gateway</html>").is_err());
+    }
+
+    #[test]
+    fn percent_encodes_track_ids() {
+        assert_eq!(percent_encode("QAAA+/x=="), "QAAA%2B%2Fx%3D%3D");
+        assert_eq!(percent_encode("a-b_c.d~"), "a-b_c.d~");
+    }
+
+    #[test]
+    fn paginates_plain_text() {
+        let line = "la ".repeat(100);
+        let text = vec![line.as_str(); 20].join("\n");
+        let lyrics = Lyrics {
+            source_name: "youtube".into(),
+            provider: None,
+            text: Some(text),
+            lines: vec![],
+        };
+
+        let (pages, current_page) = lyrics.pages(Some(1500));
+        assert_eq!(current_page, 0);
+        assert_eq!(pages.len(), 5);
+        assert!(pages.iter().all(|p| p.len() < super::PAGE_LENGTH));
+    }
+
+    /// Answers every request on a local port with `status` and `body`, like a Lavalink node would. Sends back the
+    /// request lines.
+    fn mock_node(status: &'static str, body: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (requests, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let _ = requests.send(request.lines().next().unwrap_or_default().to_string());
+
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\
+                    connection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (address, received)
+    }
+
+    async fn client(address: String) -> LavalinkClient {
+        let node = NodeBuilder {
+            hostname: address,
+            is_ssl: false,
+            events: events::Events::default(),
+            password: "youshallnotpass".into(),
+            user_id: UserId(1),
+            session_id: None,
+        };
+        LavalinkClient::new(
+            events::Events::default(),
+            vec![node],
+            NodeDistributionStrategy::round_robin(),
+        )
+        .await
+    }
+
+    /// The first request that isn't the client trying to open its websocket.
+    fn api_request(received: &mpsc::Receiver<String>) -> String {
+        received
+            .iter()
+            .find(|line| !line.contains("/websocket"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_lyrics() {
+        let (address, received) = mock_node("200 OK", SYNCED);
+        let lavalink = client(address).await;
+
+        let lyrics = request_lyrics(&lavalink.nodes[0].http, "/lyrics?track=abc")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lyrics.source_name, "spotify");
+        assert_eq!(api_request(&received), "GET /v4/lyrics?track=abc HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn requests_current_lyrics() {
+        let (address, received) = mock_node("204 No Content", "");
+        let lavalink = client(address).await;
+
+        let path = current_lyrics_path("session", GuildId(42));
+        let lyrics = request_lyrics(&lavalink.nodes[0].http, &path)
+            .await
+            .unwrap();
+        assert!(lyrics.is_none());
+        assert_eq!(
+            api_request(&received),
+            "GET /v4/sessions/session/players/42/track/lyrics HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_on_server_errors() {
+        let body = r#"{"timestamp": 1700000000000, "status": 500, "error": "Internal Server Error", "path": "/v4/lyrics"}"#;
+        let (address, _) = mock_node("500 Internal Server Error", body);
+        let lavalink = client(address).await;
+
+        let result = request_lyrics(&lavalink.nodes[0].http, "/lyrics?track=abc").await;
+        assert!(result.is_err());
+    }
+}
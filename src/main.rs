@@ -5,16 +5,37 @@ use lavalink_rs::{model::events, prelude::*};
 use poise::{serenity_prelude as serenity, FrameworkContext};
 use serenity::cache::Cache as SerenityCache;
 use songbird::SerenityInit;
+use std::sync::Arc;
 use std::time::Duration;
 
+mod autoplay;
 pub mod commands;
+mod config;
+mod failover;
+mod fair_queue;
+mod filters;
+mod history;
+mod lyrics;
 mod messages;
 pub mod music_events;
+mod now_playing;
+mod pagination;
+mod permissions;
 mod player_controller;
+mod playlists;
+mod queue_edit;
+mod search;
+mod seek;
+mod sessions;
+mod settings;
+mod sound_server;
 mod status;
+mod storage;
 mod title_parse;
 mod track_loading;
 mod util;
+mod voice_recovery;
+mod vote_skip;
 
 pub struct Data {
     pub lavalink: LavalinkClient,
@@ -23,33 +44,68 @@ pub use poise_error::anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 
 pub type Context<'a> = poise::Context<'a, Data, Error>;
 
+fn all_commands() -> Vec<poise::Command<Data, Error>> {
+    vec![
+        autoplay::autoplay(),
+        commands::clear(),
+        queue_edit::dedupe(),
+        filters::filter(),
+        seek::forward(),
+        history::history(),
+        commands::join(),
+        commands::move_bot(),
+        commands::leave(),
+        commands::loop_(),
+        lyrics::lyrics(),
+        queue_edit::move_(),
+        commands::pause(),
+        commands::play_prefix(),
+        commands::play(),
+        commands::playnext(),
+        commands::playnow(),
+        playlists::playlist(),
+        history::previous(),
+        commands::queue(),
+        queue_edit::remove(),
+        seek::replay(),
+        commands::resume(),
+        seek::rewind(),
+        search::search(),
+        seek::seek(),
+        settings::settings(),
+        commands::shuffle(),
+        commands::skip(),
+        queue_edit::skipto(),
+        commands::stop(),
+        commands::swap(),
+        commands::status(),
+        commands::volume(),
+    ]
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     init_logging();
 
+    config::init(config::Config::load().with_context(|| "Invalid configuration")?);
+    let config = config::config();
+    sound_server::start().await?;
+
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             on_error: poise_error::on_error,
             event_handler: |c, e, fc, d| Box::pin(handle_events(c, e, fc, d)),
-            commands: vec![
-                commands::clear(),
-                commands::join(),
-                commands::leave(),
-                commands::pause(),
-                commands::play(),
-                commands::queue(),
-                commands::remove(),
-                commands::resume(),
-                commands::search(),
-                commands::seek(),
-                commands::shuffle(),
-                commands::skip(),
-                commands::stop(),
-                commands::swap(),
-                commands::status(),
-            ],
+            commands: all_commands(),
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: Some("!".to_string()),
+                dynamic_prefix: Some(|ctx| {
+                    Box::pin(async move {
+                        let prefix = match ctx.guild_id {
+                            Some(guild_id) => settings::get(guild_id).await.prefix().to_string(),
+                            None => config::config().discord.prefix.clone(),
+                        };
+                        Ok(Some(prefix))
+                    })
+                }),
                 ..Default::default()
             },
             ..Default::default()
@@ -61,29 +117,38 @@ async fn main() -> Result<(), Error> {
                 let events = events::Events {
                     raw: Some(music_events::raw),
                     ready: Some(music_events::ready),
+                    player_update: Some(music_events::player_update),
+                    track_start: Some(music_events::track_start),
+                    track_end: Some(music_events::track_end),
                     track_exception: Some(music_events::track_exception),
+                    websocket_closed: Some(music_events::websocket_closed),
                     ..Default::default()
                 };
 
-                let node_local = NodeBuilder {
-                    hostname: std::env::var("LAVALINK_URL")
-                        .expect("missing $LAVALINK_URL")
-                        .to_string(),
-                    is_ssl: false,
-                    events: events::Events::default(),
-                    password: std::env::var("LAVALINK_PASSWORD")
-                        .expect("missing $LAVALINK_PASSWORD")
-                        .to_string(),
-                    user_id: ctx.cache.current_user().id.into(),
-                    session_id: None,
-                };
-
-                let lavalink = LavalinkClient::new(
+                let user_id = ctx.cache.current_user().id;
+                let nodes = config
+                    .lavalink
+                    .nodes
+                    .iter()
+                    .map(|node| NodeBuilder {
+                        hostname: node.hostname.clone(),
+                        is_ssl: node.ssl,
+                        events: events::Events::default(),
+                        password: node.password.clone(),
+                        user_id: user_id.into(),
+                        session_id: None,
+                    })
+                    .collect();
+
+                // Passing the serenity context along lets the ready hook rejoin saved sessions
+                let lavalink = LavalinkClient::new_with_data(
                     events,
-                    vec![node_local],
+                    nodes,
                     NodeDistributionStrategy::round_robin(),
+                    Arc::new(ctx.clone()),
                 )
                 .await;
+                tokio::spawn(failover::monitor_nodes(lavalink.clone()));
 
                 Ok(Data { lavalink })
             })
@@ -91,7 +156,7 @@ async fn main() -> Result<(), Error> {
         .build();
 
     let mut client = serenity::ClientBuilder::new(
-        std::env::var("DISCORD_TOKEN").expect("missing DISCORD_TOKEN"),
+        &config.discord.token,
         serenity::GatewayIntents::GUILD_MESSAGES
             | serenity::GatewayIntents::GUILD_VOICE_STATES
             | serenity::GatewayIntents::MESSAGE_CONTENT
@@ -136,8 +201,12 @@ async fn handle_events<'a>(
     use serenity::FullEvent;
 
     let res = match event {
-        FullEvent::VoiceStateUpdate { new, .. } => {
-            handle_voice_changes(&data.lavalink, VoiceChange::State(new), &ctx.cache).await
+        FullEvent::VoiceStateUpdate { old, new } => {
+            let change = VoiceChange::State {
+                old: old.as_ref(),
+                new,
+            };
+            handle_voice_changes(&data.lavalink, change, &ctx.cache).await
         }
         FullEvent::VoiceServerUpdate { event, .. } => {
             handle_voice_changes(&data.lavalink, VoiceChange::Server(event), &ctx.cache).await
@@ -150,3 +219,21 @@ async fn handle_events<'a>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::all_commands;
+
+    #[test]
+    fn play_works_as_prefix_and_slash_command() {
+        let commands = all_commands();
+
+        let (command, _, args) =
+            poise::find_command(&commands, "play --shuffle some song", false, &mut vec![]).unwrap();
+        assert!(command.prefix_action.is_some());
+        assert_eq!(args, "--shuffle some song");
+
+        let slash = commands.iter().find(|c| c.name == "play").unwrap();
+        assert!(slash.slash_action.is_some());
+    }
+}
@@ -1,11 +1,11 @@
 use crate::util::{format_millis, source_to_color, source_to_emoji, TrackUserData};
 use crate::Error;
-use futures::future;
-use futures::StreamExt;
-use lavalink_rs::model::track::{TrackData, TrackError};
-use lavalink_rs::prelude::PlayerContext;
+use lavalink_rs::model::player::Player;
+use lavalink_rs::model::track::{PlaylistInfo, TrackData, TrackError};
 use poise::serenity_prelude::{CreateEmbed, CreateEmbedAuthor};
 
+pub const QUEUE_PAGE_SIZE: usize = 10;
+
 pub fn added_to_queue(track: &TrackData) -> CreateEmbed {
     let mut embed = CreateEmbed::new()
         .title(&track.info.title)
@@ -23,6 +23,45 @@ pub fn added_to_queue(track: &TrackData) -> CreateEmbed {
     }
     embed
 }
+/// Summary of a playlist that was just queued, with `notes` on how it was added (e.g. "shuffled").
+pub fn playlist_added(
+    info: &PlaylistInfo,
+    plugin_info: &serde_json::Value,
+    tracks: &[TrackData],
+    notes: &[String],
+) -> CreateEmbed {
+    let length: u64 = tracks.iter().map(|t| t.info.length).sum();
+    let mut description = format!(
+        "Added {} tracks to queue, {}",
+        tracks.len(),
+        format_millis(length)
+    );
+    if !notes.is_empty() {
+        description.push_str(&format!("\n-# {}", notes.join(", ")));
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title(&info.name)
+        .description(description);
+    if let Some(first) = tracks.first() {
+        let source = &first.info.source_name;
+        embed = embed
+            .author(CreateEmbedAuthor::new("Playlist").icon_url(source_to_emoji(source).url()))
+            .color(source_to_color(source));
+    }
+    if let Some(url) = plugin_info["url"].as_str() {
+        embed = embed.url(url)
+    }
+    // LavaSrc provides playlist artwork, otherwise fall back to the first track's
+    let artwork = plugin_info["artworkUrl"]
+        .as_str()
+        .or_else(|| tracks.first()?.info.artwork_url.as_deref());
+    if let Some(img) = artwork {
+        embed = embed.thumbnail(img)
+    }
+    embed
+}
+
 pub fn recovered_with_alternative(
     track: &TrackData,
     error: &TrackError,
@@ -85,65 +124,76 @@ pub fn search_results(results: &[Vec<TrackData>]) -> CreateEmbed {
     CreateEmbed::new().description(description)
 }
 
-pub async fn queue_message(player: PlayerContext) -> Result<String, Error> {
-    let queue = player.get_queue();
-    let player_data = player.get_player().await?;
+pub fn queue_line(index: usize, track: &TrackData) -> String {
+    let requester = TrackUserData::try_from(track)
+        .map(|d| format!(" | {}", d.requested_by()))
+        .unwrap_or_default();
 
-    let max = queue.get_count().await?.min(9);
+    if let Some(uri) = &track.info.uri {
+        format!(
+            "{} -> [{} - {}](<{}>){}",
+            index + 1,
+            track.info.author,
+            track.info.title,
+            uri,
+            requester
+        )
+    } else {
+        format!(
+            "{} -> {} - {}{}",
+            index + 1,
+            track.info.author,
+            track.info.title,
+            requester
+        )
+    }
+}
 
-    let queue_message = queue
-        .enumerate()
-        .take_while(|(idx, _)| future::ready(*idx < max))
-        .map(|(idx, x)| {
-            if let Some(uri) = &x.track.info.uri {
-                format!(
-                    "{} -> [{} - {}](<{}>) | Requested by <@!{}>",
-                    idx + 1,
-                    x.track.info.author,
-                    x.track.info.title,
-                    uri,
-                    TrackUserData::try_from(&x.track).unwrap().requester_id.0
-                )
-            } else {
-                format!(
-                    "{} -> {} - {} | Requested by <@!{}",
-                    idx + 1,
-                    x.track.info.author,
-                    x.track.info.title,
-                    TrackUserData::try_from(&x.track).unwrap().requester_id.0
-                )
-            }
-        })
-        .collect::<Vec<_>>()
-        .await
-        .join("\n");
+pub fn now_playing_line(player_data: &Player) -> Result<String, Error> {
+    let Some(track) = &player_data.track else {
+        return Ok("Now playing: nothing".to_string());
+    };
 
-    let now_playing_message = if let Some(track) = player_data.track {
-        let time_s = player_data.state.position / 1000 % 60;
-        let time_m = player_data.state.position / 1000 / 60;
-        let time = format!("{:02}:{:02}", time_m, time_s);
+    let time_s = player_data.state.position / 1000 % 60;
+    let time_m = player_data.state.position / 1000 / 60;
+    let time = format!("{:02}:{:02}", time_m, time_s);
 
-        if let Some(uri) = &track.info.uri {
-            format!(
-                "Now playing: [{} - {}](<{}>) | {}, Requested by <@!{}>",
-                track.info.author,
-                track.info.title,
-                uri,
-                time,
-                TrackUserData::try_from(&track)?.requester_id.0
-            )
-        } else {
-            format!(
-                "Now playing: {} - {} | {}, Requested by <@!{}>",
-                track.info.author,
-                track.info.title,
-                time,
-                TrackUserData::try_from(&track)?.requester_id.0
-            )
-        }
+    Ok(if let Some(uri) = &track.info.uri {
+        format!(
+            "Now playing: [{} - {}](<{}>) | {}, {}",
+            track.info.author,
+            track.info.title,
+            uri,
+            time,
+            TrackUserData::try_from(track)?.requested_by()
+        )
     } else {
-        "Now playing: nothing".to_string()
-    };
+        format!(
+            "Now playing: {} - {} | {}, {}",
+            track.info.author,
+            track.info.title,
+            time,
+            TrackUserData::try_from(track)?.requested_by()
+        )
+    })
+}
 
-    Ok(format!("{}\n\n{}", now_playing_message, queue_message))
+/// Splits queue lines into embeds of [`QUEUE_PAGE_SIZE`] lines, each headed by `header`.
+pub fn queue_pages(header: &str, lines: &[String]) -> Vec<CreateEmbed> {
+    if lines.is_empty() {
+        return vec![
+            CreateEmbed::new()
+                .title("Queue")
+                .description(format!("{header}\n\n*Nothing here*")),
+        ];
+    }
+
+    lines
+        .chunks(QUEUE_PAGE_SIZE)
+        .map(|chunk| {
+            CreateEmbed::new()
+                .title("Queue")
+                .description(format!("{header}\n\n{}", chunk.join("\n")))
+        })
+        .collect()
 }
@@ -1,6 +1,9 @@
-use crate::player_controller::PlayerController;
+use crate::history::TrackEnding;
+use crate::player_controller::{PlayerController, PlayerData};
 use crate::*;
-use lavalink_rs::model::events::TrackException;
+use lavalink_rs::model::events::{
+    PlayerUpdate, TrackEnd, TrackException, TrackStart, WebSocketClosed,
+};
 use lavalink_rs::model::http::UpdatePlayer;
 use lavalink_rs::{hook, model::events};
 use poise::serenity_prelude::{
@@ -35,8 +38,89 @@ pub async fn raw(_: LavalinkClient, session_id: String, event: &serde_json::Valu
 
 #[hook]
 pub async fn ready(lavalink: LavalinkClient, session_id: String, event: &events::Ready) {
-    lavalink.delete_all_player_contexts().await.unwrap();
     info!("{:?} -> {:?}", session_id, event);
+
+    // Only this node lost its players, the others may still be playing
+    let node = lavalink
+        .nodes
+        .iter()
+        .find(|n| **n.session_id.load() == session_id);
+    if let Some(node) = node.filter(|_| !event.resumed) {
+        failover::forget_node_players(&lavalink, node.id);
+    }
+    if let Some(node) = node.cloned() {
+        tokio::spawn(async move { util::check_join_sound(&node).await });
+    }
+
+    let ctx = match lavalink.data::<serenity::Context>() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            error!("Can't resume sessions without a serenity context: {e:?}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        if let Err(e) = sessions::resume_all(&lavalink, &ctx).await {
+            error!("Failed to resume sessions: {e:#}");
+        }
+    });
+}
+
+#[hook]
+pub async fn player_update(lavalink: LavalinkClient, _session_id: String, event: &PlayerUpdate) {
+    let Some(player_ctx) = lavalink.get_player_context(event.guild_id) else {
+        return;
+    };
+
+    let controller = PlayerController::from(player_ctx);
+
+    if let Err(e) = controller.update_now_playing(event.state.position).await {
+        error!("Failed to update now playing message: {e:#}");
+    }
+
+    if let Err(e) = sessions::save_periodically(&controller).await {
+        error!("Failed to save session: {e:#}");
+    }
+}
+
+#[hook]
+pub async fn track_start(lavalink: LavalinkClient, _session_id: String, event: &TrackStart) {
+    let Some(player_ctx) = lavalink.get_player_context(event.guild_id) else {
+        return;
+    };
+    let controller = PlayerController::from(player_ctx);
+    history::record_start(event.guild_id, &event.track);
+
+    let mode = controller.data.loop_mode();
+    if let Err(e) = controller.queue_loop_copy(mode, event.track.clone()) {
+        error!("Failed to queue looped track: {e:#?}");
+    }
+
+    if let Err(e) = controller.autoplay_if_drained(&event.track).await {
+        error!("Failed to autoplay: {e:#}");
+    }
+
+    if let Err(e) = controller.post_now_playing(&event.track).await {
+        error!("Failed to post now playing message: {e:#}");
+    }
+
+    if let Err(e) = sessions::save(&controller).await {
+        error!("Failed to save session: {e:#}");
+    }
+}
+
+#[hook]
+pub async fn track_end(lavalink: LavalinkClient, _session_id: String, event: &TrackEnd) {
+    let Some(player_ctx) = lavalink.get_player_context(event.guild_id) else {
+        return;
+    };
+    let controller = PlayerController::from(player_ctx);
+    history::record_end(event.guild_id, &event.track, (&event.reason).into());
+    vote_skip::end_vote(event.guild_id);
+
+    if let Err(e) = controller.clear_now_playing(Some(&event.track)).await {
+        error!("Failed to delete now playing message: {e:#}");
+    }
 }
 
 #[hook]
@@ -83,10 +167,24 @@ async fn _track_exception(
     let alternatives = controller.find_alternative_tracks(track).await;
     dbg!(&alternatives);
     if !alternatives.is_empty() {
+        history::record_end(
+            player_data.guild_id,
+            track,
+            TrackEnding::ReplacedByAlternative,
+        );
         let best = alternatives.first().unwrap().1.clone();
         let embed = messages::recovered_with_alternative(track, exception, &alternatives);
         info!("Queueing alternative track");
-        controller.ctx.get_queue().push_to_front(best)?;
+        // The failed track shouldn't come back through the loop
+        controller
+            .remove_loop_copy(player_data.loop_mode(), track)
+            .await?;
+        controller
+            .edit_user_queue(|queue| {
+                queue.push_front(best.into());
+                Ok(())
+            })
+            .await?;
         player_data
             .text_channel
             .send_message(player_data.http.clone(), CreateMessage::new().embed(embed))
@@ -94,6 +192,7 @@ async fn _track_exception(
         return Ok(());
     }
 
+    history::record_end(player_data.guild_id, track, TrackEnding::Failed);
     let embed = CreateEmbed::new()
         .author(CreateEmbedAuthor::new("Error during Playback"))
         .color(Colour::GOLD)
@@ -110,8 +209,31 @@ async fn _track_exception(
     Ok(())
 }
 
+#[hook]
+pub async fn websocket_closed(
+    lavalink: LavalinkClient,
+    _session_id: String,
+    event: &WebSocketClosed,
+) {
+    warn!(
+        "Voice connection in guild {} closed with code {}: {}",
+        event.guild_id.0, event.code, event.reason
+    );
+    // 4014 means Discord disconnected us on purpose, e.g. to move us. Voice state updates take care of that.
+    if event.code == 4014 {
+        return;
+    }
+    if let Some(player_ctx) = lavalink.get_player_context(event.guild_id) {
+        tokio::spawn(PlayerController::from(player_ctx).recover_voice());
+    }
+}
+
 pub enum VoiceChange<'a> {
-    State(&'a VoiceState),
+    /// The previous state, if it was cached, and the new one
+    State {
+        old: Option<&'a VoiceState>,
+        new: &'a VoiceState,
+    },
     Server(&'a VoiceServerUpdateEvent),
 }
 
@@ -121,13 +243,19 @@ pub async fn handle_voice_changes(
     cache: &Arc<Cache>,
 ) -> Result<()> {
     let guild_id = match change {
-        VoiceChange::State(VoiceState {
-            session_id,
-            channel_id,
-            guild_id: Some(guild_id),
-            user_id,
-            ..
-        }) => {
+        VoiceChange::State {
+            old,
+            new:
+                VoiceState {
+                    session_id,
+                    channel_id,
+                    guild_id: Some(guild_id),
+                    user_id,
+                    suppress,
+                    request_to_speak_timestamp,
+                    ..
+                },
+        } => {
             if user_id != &cache.current_user().id {
                 return Ok(());
             }
@@ -137,6 +265,27 @@ pub async fn handle_voice_changes(
                 *user_id,
                 session_id.clone(),
             );
+            if channel_id.is_none() {
+                // Kicked, or the connection dropped. Leaving on purpose removes the player first.
+                if let Some(player_ctx) = lavalink.get_player_context(*guild_id) {
+                    tokio::spawn(PlayerController::from(player_ctx).recover_voice());
+                }
+                return Ok(());
+            }
+            // Only in stages, after someone moved us into one. Staying in the audience afterwards is up to the
+            // moderators.
+            let moved = old.and_then(|old| old.channel_id) != *channel_id;
+            if let (true, true, None, Some(channel_id), Some(player_ctx)) = (
+                moved,
+                *suppress,
+                request_to_speak_timestamp,
+                channel_id,
+                lavalink.get_player_context(*guild_id),
+            ) {
+                let data = PlayerData::from(&player_ctx);
+                let channel_id = *channel_id;
+                tokio::spawn(async move { data.speak_if_stage(channel_id).await });
+            }
             *guild_id
         }
         VoiceChange::Server(VoiceServerUpdateEvent {
@@ -0,0 +1,83 @@
+use crate::player_controller::PlayerController;
+use crate::status::StatusBuilder;
+use crate::util::TrackUserData;
+use crate::*;
+use lavalink_rs::model::track::TrackData;
+use poise::serenity_prelude::{CreateMessage, EditMessage, MessageId};
+
+/// The message announcing the current track, which is kept up to date while it plays.
+pub struct NowPlaying {
+    pub message: MessageId,
+    /// The track the message is about
+    pub encoded: String,
+}
+
+impl PlayerController {
+    /// Posts a now-playing message for a track that just started, replacing the previous one.
+    pub async fn post_now_playing(&self, track: &TrackData) -> Result<()> {
+        if let Err(e) = self.clear_now_playing(None).await {
+            error!("Failed to delete previous now playing message: {e:#}");
+        }
+
+        // Don't announce the join announcement
+        if TrackUserData::try_from(track).is_err() {
+            return Ok(());
+        }
+
+        let embed = StatusBuilder::new(&self.ctx).await?.player_embed();
+        let message = self
+            .data
+            .text_channel
+            .send_message(&self.data.http, CreateMessage::new().embed(embed))
+            .await?;
+
+        *self.data.now_playing.lock() = Some(NowPlaying {
+            message: message.id,
+            encoded: track.encoded.clone(),
+        });
+        Ok(())
+    }
+
+    /// Refreshes the progress bar of the now-playing message, if there is one.
+    pub async fn update_now_playing(&self, position: u64) -> Result<()> {
+        let Some(message) = self.data.now_playing.lock().as_ref().map(|np| np.message) else {
+            return Ok(());
+        };
+
+        let embed = StatusBuilder::new(&self.ctx)
+            .await?
+            .at_position(position)
+            .player_embed();
+        let edited = self
+            .data
+            .text_channel
+            .edit_message(&self.data.http, message, EditMessage::new().embed(embed))
+            .await;
+        if edited.is_err() {
+            // Most likely deleted by someone, don't keep trying
+            self.data.now_playing.lock().take();
+        }
+        edited?;
+        Ok(())
+    }
+
+    /// Deletes the now-playing message. If `ended` is given, only if the message is about that track, so a late
+    /// track end event doesn't remove the message for the next track.
+    pub async fn clear_now_playing(&self, ended: Option<&TrackData>) -> Result<()> {
+        let now_playing = {
+            let mut guard = self.data.now_playing.lock();
+            let matches = guard
+                .as_ref()
+                .is_some_and(|np| ended.is_none_or(|t| t.encoded == np.encoded));
+            if matches { guard.take() } else { None }
+        };
+
+        if let Some(np) = now_playing {
+            self.data
+                .text_channel
+                .delete_message(&self.data.http, np.message)
+                .await?;
+        }
+        Ok(())
+    }
+}
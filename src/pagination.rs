@@ -0,0 +1,100 @@
+use crate::*;
+use poise::CreateReply;
+use poise::serenity_prelude::{
+    ButtonStyle, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+
+/// Replies with `pages`, starting at `start`. Buttons to flip through them are only usable by the invoker and
+/// disappear after two minutes without use.
+pub async fn paginate(ctx: Context<'_>, pages: Vec<CreateEmbed>, start: usize) -> Result<()> {
+    let last = pages.len().saturating_sub(1);
+    let mut page = start.min(last);
+
+    let render = |page: usize| {
+        pages
+            .get(page)
+            .cloned()
+            .unwrap_or_default()
+            .footer(CreateEmbedFooter::new(format!(
+                "Page {}/{}",
+                page + 1,
+                pages.len().max(1)
+            )))
+    };
+
+    if last == 0 {
+        ctx.send(CreateReply::default().embed(render(0))).await?;
+        return Ok(());
+    }
+
+    let prefix = ctx.id().to_string();
+    let buttons = |page: usize| {
+        let button = |action: &str, label: &str, disabled: bool| {
+            CreateButton::new(format!("{prefix}-{action}"))
+                .label(label)
+                .style(ButtonStyle::Secondary)
+                .disabled(disabled)
+        };
+        vec![CreateActionRow::Buttons(vec![
+            button("first", "⏮", page == 0),
+            button("prev", "◀", page == 0),
+            button("next", "▶", page == last),
+            button("last", "⏭", page == last),
+        ])]
+    };
+
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .embed(render(page))
+                .components(buttons(page)),
+        )
+        .await?;
+    let message = handle.message().await?.into_owned();
+
+    while let Some(interaction) = message
+        .await_component_interaction(&ctx.serenity_context().shard)
+        .timeout(Duration::from_secs(120))
+        .await
+    {
+        if interaction.user.id != ctx.author().id {
+            let response = CreateInteractionResponseMessage::new()
+                .content("Only the person who used the command can do that")
+                .ephemeral(true);
+            interaction
+                .create_response(ctx, CreateInteractionResponse::Message(response))
+                .await?;
+            continue;
+        }
+
+        let action = interaction
+            .data
+            .custom_id
+            .strip_prefix(&format!("{prefix}-"));
+        page = match action {
+            Some("first") => 0,
+            Some("prev") => page.saturating_sub(1),
+            Some("next") => (page + 1).min(last),
+            Some("last") => last,
+            _ => continue,
+        };
+
+        let response = CreateInteractionResponseMessage::new()
+            .embed(render(page))
+            .components(buttons(page));
+        interaction
+            .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+            .await?;
+    }
+
+    handle
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(render(page))
+                .components(vec![]),
+        )
+        .await?;
+    Ok(())
+}
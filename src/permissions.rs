@@ -0,0 +1,107 @@
+use crate::util::{TrackUserData, check_if_in_channel};
+use crate::*;
+use lavalink_rs::model::track::TrackData;
+use poise::serenity_prelude::{ChannelId, Guild, Permissions, UserId};
+
+/// Voice channel the user is connected to, according to the cache.
+pub fn voice_channel_of(guild: &Guild, user_id: UserId) -> Option<ChannelId> {
+    guild
+        .voice_states
+        .get(&user_id)
+        .and_then(|voice_state| voice_state.channel_id)
+}
+
+/// Users other than bots in a voice channel.
+pub fn humans_in_channel(guild: &Guild, channel_id: ChannelId) -> Vec<UserId> {
+    let is_bot = |user_id: &UserId| guild.members.get(user_id).is_some_and(|m| m.user.bot);
+
+    guild
+        .voice_states
+        .values()
+        .filter(|vs| vs.channel_id == Some(channel_id))
+        .filter(|vs| match &vs.member {
+            Some(member) => !member.user.bot,
+            None => !is_bot(&vs.user_id),
+        })
+        .map(|vs| vs.user_id)
+        .collect()
+}
+
+/// The player, if the invoker is in the same voice channel as the bot.
+pub async fn require_same_channel(ctx: Context<'_>) -> Result<PlayerContext> {
+    let player = check_if_in_channel(ctx).await?;
+
+    let same_channel = {
+        let guild = ctx.guild().with_context(|| "Guild isn't cached")?;
+        let own_channel = voice_channel_of(&guild, ctx.cache().current_user().id);
+        own_channel.is_some() && own_channel == voice_channel_of(&guild, ctx.author().id)
+    };
+    if !same_channel {
+        user_error!("You need to be in my voice channel to do that")
+    }
+
+    Ok(player)
+}
+
+/// Whether the invoker may control playback for everyone: Admins (Manage Server), members with the DJ role if one is
+/// set up, or anyone alone with the bot.
+pub async fn is_dj(ctx: Context<'_>) -> Result<bool> {
+    if is_admin_or_dj_role(ctx).await? {
+        return Ok(true);
+    }
+
+    let guild = ctx.guild().with_context(|| "Guild isn't cached")?;
+    let alone_with_bot = voice_channel_of(&guild, ctx.author().id)
+        .is_some_and(|channel| humans_in_channel(&guild, channel) == [ctx.author().id]);
+    Ok(alone_with_bot)
+}
+
+/// Whether the invoker is an admin (Manage Server) or has the DJ role, if one is set up.
+pub async fn is_admin_or_dj_role(ctx: Context<'_>) -> Result<bool> {
+    let guild_id = ctx.guild_id().with_context(|| "Not in a guild")?;
+    let dj_role = settings::get(guild_id).await.dj_role;
+    let member = ctx
+        .author_member()
+        .await
+        .with_context(|| "Failed to get the invoking member")?;
+    if dj_role.is_some_and(|role| member.roles.contains(&role)) {
+        return Ok(true);
+    }
+
+    let guild = ctx.guild().with_context(|| "Guild isn't cached")?;
+    let permissions = member.permissions.unwrap_or_else(|| {
+        guild
+            .channels
+            .get(&ctx.channel_id())
+            .map(|channel| guild.user_permissions_in(channel, &member))
+            .unwrap_or_else(Permissions::empty)
+    });
+    Ok(permissions.manage_guild())
+}
+
+/// The player, if the invoker is in the bot's voice channel and a DJ (see [`is_dj`]).
+pub async fn require_dj(ctx: Context<'_>) -> Result<PlayerContext> {
+    let player = require_same_channel(ctx).await?;
+    if !is_dj(ctx).await? {
+        user_error!("Only DJs can do that")
+    }
+    Ok(player)
+}
+
+/// Whether the invoker requested the track or is a DJ.
+pub async fn is_dj_or_requester(ctx: Context<'_>, track: &TrackData) -> Result<bool> {
+    Ok(is_requester(ctx, track) || is_dj(ctx).await?)
+}
+
+/// Whether the invoker requested the track.
+pub fn is_requester(ctx: Context<'_>, track: &TrackData) -> bool {
+    TrackUserData::try_from(track).is_ok_and(|data| data.requester_id.0 == ctx.author().id.get())
+}
+
+/// Fails unless the invoker requested the track or is a DJ.
+pub async fn require_dj_or_requester(ctx: Context<'_>, track: &TrackData) -> Result<()> {
+    if !is_dj_or_requester(ctx, track).await? {
+        user_error!("Only the requester or a DJ can do that")
+    }
+    Ok(())
+}
@@ -1,12 +1,35 @@
-use crate::util::{get_own_voice_channel, leave};
+use crate::filters::ActiveFilters;
+use crate::history::TrackEnding;
+use crate::now_playing::NowPlaying;
+use crate::permissions::humans_in_channel;
+use crate::util::{TrackUserData, get_own_voice_channel, leave, speak_if_stage};
 use crate::*;
 use chrono::{DateTime, TimeDelta, Utc};
+use lavalink_rs::model::track::TrackData;
+use lavalink_rs::prelude::TrackInQueue;
 use parking_lot::Mutex;
-use poise::serenity_prelude::{ChannelId, Http};
+use poise::serenity_prelude::{ChannelId, Http, UserId};
+use serde::{Deserialize, Serialize};
 use songbird::Songbird;
+use std::collections::VecDeque;
 use std::num::NonZeroU64;
 use std::ops::Sub;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What to repeat once the current track ends.
+#[derive(
+    Default, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum LoopMode {
+    #[default]
+    #[name = "off"]
+    Off,
+    #[name = "track"]
+    Track,
+    #[name = "queue"]
+    Queue,
+}
 
 pub struct PlayerData {
     pub lavalink: LavalinkClient,
@@ -16,12 +39,25 @@ pub struct PlayerData {
     pub songbird: Arc<Songbird>,
     pub guild_id: GuildId,
     pub alone_since: Mutex<Option<DateTime<Utc>>>,
+    pub loop_mode: Mutex<LoopMode>,
+    pub filters: Mutex<ActiveFilters>,
+    pub now_playing: Mutex<Option<NowPlaying>>,
+    /// Set while getting a lost voice connection back
+    pub recovering: AtomicBool,
+    /// Set while paused because nobody was listening, so playback resumes once someone is back
+    pub auto_paused: AtomicBool,
+    /// Held while editing the queue, see [`PlayerController::edit_user_queue`]
+    pub queue_lock: tokio::sync::Mutex<()>,
 }
 
 impl PlayerData {
     pub fn from(ctx: &PlayerContext) -> Arc<PlayerData> {
         ctx.data().expect("Failed to get PlayerContextData")
     }
+    pub fn loop_mode(&self) -> LoopMode {
+        *self.loop_mode.lock()
+    }
+
     pub fn mark_alone(&self) {
         let mut guard = self.alone_since.lock();
         if guard.is_none() {
@@ -41,30 +77,91 @@ impl PlayerData {
             .is_some_and(|ts| delta < Utc::now().sub(ts))
     }
 
+    /// See [`speak_if_stage`]. Failing that only leaves the bot muted, so it's not an error.
+    pub async fn speak_if_stage(&self, channel: ChannelId) {
+        if let Err(e) = speak_if_stage(&self.http, &self.cache, self.guild_id.0, channel).await {
+            warn!("Failed to speak in stage channel {channel}: {e:#}");
+        }
+    }
+
+    /// Humans in `channel` who can hear the bot, i.e. aren't deafened. Counted from voice states, since members in a
+    /// stage's audience are often not cached.
+    fn listener_count(&self, channel: ChannelId) -> Option<usize> {
+        let guild = self.cache.guild(self.guild_id.0)?;
+        let own_id = self.cache.current_user().id;
+        let can_hear = |user: &UserId| {
+            guild
+                .voice_states
+                .get(user)
+                .is_some_and(|vs| !vs.self_deaf && !vs.deaf)
+        };
+        let count = humans_in_channel(&guild, channel)
+            .iter()
+            .filter(|&user| *user != own_id && can_hear(user))
+            .count();
+        Some(count)
+    }
+
+    /// Pauses while nobody is listening and resumes once someone is back. Leaves if nobody came back for the alone
+    /// timeout.
     async fn player_watchdog(self: Arc<Self>) {
         loop {
             // Give the player time to initialize
             tokio::time::sleep(Duration::from_secs(10)).await;
 
-            if self.lavalink.get_player_context(self.guild_id).is_none() {
+            let Some(player_ctx) = self.lavalink.get_player_context(self.guild_id) else {
                 break; // Player has quit
             };
 
-            let channel = get_own_voice_channel(&self.cache, self.guild_id.0).unwrap();
-            let members = channel.members(&self.cache).unwrap();
+            // Losing the connection is up to voice recovery
+            let Ok(channel) = get_own_voice_channel(&self.cache, self.guild_id.0) else {
+                continue;
+            };
+            let Some(listeners) = self.listener_count(channel.id) else {
+                continue;
+            };
 
-            if members.len() > 1 {
+            let timeout = settings::get(self.guild_id).await.alone_timeout();
+            if listeners > 0 {
                 self.reset_alone();
-            } else if self.is_alone_for(TimeDelta::minutes(3)) {
+                if self.auto_paused.swap(false, Ordering::SeqCst) {
+                    debug!("Resuming player for guild {}", self.guild_id.0);
+                    if let Err(e) = player_ctx.set_pause(false).await {
+                        error!("Failed to resume player: {e:#}");
+                    }
+                }
+            } else if self.is_alone_for(TimeDelta::from_std(timeout).unwrap_or(TimeDelta::MAX)) {
                 debug!("Removing player for guild {}", self.guild_id.0);
-                leave(&self.lavalink, &self.songbird, self.guild_id)
-                    .await
-                    .unwrap();
+                if let Err(e) = leave(&self.lavalink, &self.songbird, self.guild_id).await {
+                    error!("Failed to leave after being alone: {e:#}");
+                }
             } else {
                 self.mark_alone();
+                if let Err(e) = self.pause_for_nobody(&player_ctx).await {
+                    error!("Failed to pause player: {e:#}");
+                }
             }
         }
     }
+
+    /// Pauses the current track, unless nothing is playing or it is paused already.
+    async fn pause_for_nobody(&self, player_ctx: &PlayerContext) -> Result<()> {
+        let player = player_ctx.get_player().await?;
+        if player.paused || player.track.is_none() || self.auto_paused.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        debug!("Pausing player for guild {}", self.guild_id.0);
+        player_ctx.set_pause(true).await?;
+        self.auto_paused.store(true, Ordering::SeqCst);
+        self.text_channel
+            .say(
+                &self.http,
+                "Nobody's listening, pausing until someone is back",
+            )
+            .await?;
+        Ok(())
+    }
 }
 
 pub struct PlayerController {
@@ -74,19 +171,27 @@ pub struct PlayerController {
 
 impl PlayerController {
     pub async fn init(
-        ctx: &Context<'_>,
         lavalink: &LavalinkClient,
+        ctx: &serenity::Context,
         songbird: Arc<Songbird>,
+        guild_id: impl Into<GuildId>,
         vc_id: ChannelId,
+        text_channel: ChannelId,
     ) -> Result<PlayerController> {
         let data = Arc::new(PlayerData {
             lavalink: lavalink.clone(),
-            text_channel: ctx.channel_id(),
-            http: ctx.serenity_context().http.clone(),
-            cache: ctx.serenity_context().cache.clone(),
+            text_channel,
+            http: ctx.http.clone(),
+            cache: ctx.cache.clone(),
             songbird,
-            guild_id: ctx.guild_id().unwrap().into(),
+            guild_id: guild_id.into(),
             alone_since: Mutex::new(None),
+            loop_mode: Mutex::new(LoopMode::default()),
+            filters: Mutex::new(ActiveFilters::default()),
+            now_playing: Mutex::new(None),
+            recovering: AtomicBool::new(false),
+            auto_paused: AtomicBool::new(false),
+            queue_lock: tokio::sync::Mutex::new(()),
         });
         let guild_id = data.guild_id;
 
@@ -95,6 +200,7 @@ impl PlayerController {
             .join_gateway(NonZeroU64::new(guild_id.0).unwrap(), vc_id)
             .await
             .with_context(|| "Failed to join voice channel")?;
+        data.speak_if_stage(vc_id).await;
 
         let player_context = data
             .lavalink
@@ -115,4 +221,147 @@ impl PlayerController {
             ctx,
         }
     }
+
+    /// Replaces the queue and continues `current` (if any) at `position` milliseconds, e.g. after the player was
+    /// recreated.
+    pub async fn restore_playback(
+        &self,
+        mut queue: VecDeque<TrackInQueue>,
+        current: Option<TrackData>,
+        position: u64,
+        paused: bool,
+    ) -> Result<()> {
+        let filters = self.data.filters.lock().clone();
+        if !filters.is_empty() {
+            self.ctx.set_filters(filters.to_filters()).await?;
+        }
+
+        let Some(current) = current else {
+            self.ctx.get_queue().replace(queue)?;
+            return Ok(());
+        };
+
+        let mut current = TrackInQueue::from(current);
+        current.start_time = Some(Duration::from_millis(position));
+        queue.push_front(current);
+
+        self.ctx.get_queue().replace(queue)?;
+        self.ctx.skip()?;
+        if paused {
+            self.ctx.set_pause(true).await?;
+        }
+        Ok(())
+    }
+
+    /// Switches the loop mode, moving the looped copy of the current track (if any) to where the new mode expects it.
+    pub async fn set_loop_mode(&self, mode: LoopMode) -> Result<()> {
+        let previous = std::mem::replace(&mut *self.data.loop_mode.lock(), mode);
+        let Some(current) = self.ctx.get_player().await?.track else {
+            return Ok(());
+        };
+
+        self.remove_loop_copy(previous, &current).await?;
+        self.queue_loop_copy(mode, current)
+    }
+
+    /// Queues a copy of a track that just started, according to the loop mode.
+    ///
+    /// Doing this on track start rather than track end means lavalink-rs' own queue handling picks up the copy,
+    /// instead of us racing it for the next track.
+    pub fn queue_loop_copy(&self, mode: LoopMode, track: TrackData) -> Result<()> {
+        // Only loop what users requested, not e.g. the join announcement
+        if TrackUserData::try_from(&track).is_err() {
+            return Ok(());
+        }
+
+        let queue = self.ctx.get_queue();
+        match mode {
+            LoopMode::Off => {}
+            LoopMode::Track => queue.push_to_front(track)?,
+            LoopMode::Queue => queue.push_to_back(track)?,
+        }
+        Ok(())
+    }
+
+    /// The queue as users see it, i.e. without the copy queued by [`Self::queue_loop_copy`]. The copy is returned
+    /// separately, so [`Self::set_user_queue`] can put it back.
+    pub async fn user_queue(&self) -> Result<(VecDeque<TrackInQueue>, Option<TrackInQueue>)> {
+        let mut queue = self.ctx.get_queue().get_queue().await?;
+        let Some(current) = self.ctx.get_player().await?.track else {
+            return Ok((queue, None));
+        };
+
+        let is_copy =
+            |t: Option<&TrackInQueue>| t.is_some_and(|t| t.track.encoded == current.encoded);
+        let copy = match self.data.loop_mode() {
+            LoopMode::Track if is_copy(queue.front()) => queue.pop_front(),
+            LoopMode::Queue if is_copy(queue.back()) => queue.pop_back(),
+            _ => None,
+        };
+        Ok((queue, copy))
+    }
+
+    /// Edits the queue as users see it, see [`Self::user_queue`]. Holds [`PlayerData::queue_lock`] from reading the
+    /// queue until it is replaced, so concurrent edits don't get lost.
+    pub async fn edit_user_queue<R>(
+        &self,
+        edit: impl FnOnce(&mut VecDeque<TrackInQueue>) -> Result<R>,
+    ) -> Result<R> {
+        let _lock = self.data.queue_lock.lock().await;
+        let (mut queue, loop_copy) = self.user_queue().await?;
+        let result = edit(&mut queue)?;
+        self.set_user_queue(queue, loop_copy)?;
+        Ok(result)
+    }
+
+    /// Replaces the queue with one from [`Self::user_queue`], putting the loop copy back where the loop mode expects it.
+    fn set_user_queue(
+        &self,
+        mut queue: VecDeque<TrackInQueue>,
+        loop_copy: Option<TrackInQueue>,
+    ) -> Result<()> {
+        if let Some(copy) = loop_copy {
+            match self.data.loop_mode() {
+                LoopMode::Track => queue.push_front(copy),
+                _ => queue.push_back(copy),
+            }
+        }
+        self.ctx.get_queue().replace(queue)?;
+        Ok(())
+    }
+
+    /// Removes the copy queued by [`Self::queue_loop_copy`], if it is still where `mode` put it.
+    pub async fn remove_loop_copy(&self, mode: LoopMode, current: &TrackData) -> Result<()> {
+        let queue = self.ctx.get_queue();
+        let index = match mode {
+            LoopMode::Off => return Ok(()),
+            LoopMode::Track => 0,
+            LoopMode::Queue => match queue.get_count().await? {
+                0 => return Ok(()),
+                n => n - 1,
+            },
+        };
+
+        let is_copy = queue
+            .get_track(index)
+            .await?
+            .is_some_and(|t| t.track.encoded == current.encoded);
+        if is_copy {
+            queue.remove(index)?;
+        }
+        Ok(())
+    }
+
+    /// Skips `current`, recording it as skipped in the history.
+    pub async fn skip_current(&self, current: &TrackData) -> Result<()> {
+        history::record_end(self.data.guild_id, current, TrackEnding::Skipped);
+
+        // Otherwise we'd just skip to the looped copy of the same track
+        if self.data.loop_mode() == LoopMode::Track {
+            self.remove_loop_copy(LoopMode::Track, current).await?;
+        }
+
+        self.ctx.skip()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,472 @@
+use crate::player_controller::PlayerController;
+use crate::storage;
+use crate::track_loading::{is_direct_query, raise_for_load_type};
+use crate::util::{TrackUserData, check_if_in_channel, format_millis};
+use crate::*;
+use itertools::Itertools;
+use lavalink_rs::model::track::TrackData;
+use poise::CreateReply;
+use poise::serenity_prelude::{CreateEmbed, UserId};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const MAX_NAME_LENGTH: usize = 100;
+/// Held by [`modify_all_own`] from loading playlists until they're saved, so concurrent changes don't overwrite each
+/// other.
+static UPDATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Enough of a track to list it without asking Lavalink to decode it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlaylistTrack {
+    pub encoded: String,
+    pub title: String,
+    pub author: String,
+    /// In milliseconds
+    pub length: u64,
+    pub uri: Option<String>,
+}
+
+impl From<&TrackData> for PlaylistTrack {
+    fn from(track: &TrackData) -> Self {
+        Self {
+            encoded: track.encoded.clone(),
+            title: track.info.title.clone(),
+            author: track.info.author.clone(),
+            length: track.info.length,
+            uri: track.info.uri.clone(),
+        }
+    }
+}
+
+impl PlaylistTrack {
+    fn line(&self, index: usize) -> String {
+        let name = match &self.uri {
+            Some(uri) => format!("[{} - {}](<{uri}>)", self.author, self.title),
+            None => format!("{} - {}", self.author, self.title),
+        };
+        format!("{} -> {name} `[{}]`", index + 1, format_millis(self.length))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Playlist {
+    pub name: String,
+    pub owner_id: u64,
+    /// Guild whose members can see and play the playlist, if any
+    pub shared_in: Option<u64>,
+    pub tracks: Vec<PlaylistTrack>,
+}
+
+impl Playlist {
+    fn summary(&self) -> String {
+        let length: u64 = self.tracks.iter().map(|t| t.length).sum();
+        format!(
+            "**{}** by <@!{}>: {} tracks, {}",
+            self.name,
+            self.owner_id,
+            self.tracks.len(),
+            format_millis(length)
+        )
+    }
+}
+
+fn playlists_dir() -> PathBuf {
+    storage::data_dir().join("playlists")
+}
+
+fn playlists_path(user_id: UserId) -> PathBuf {
+    playlists_dir().join(format!("{user_id}.json"))
+}
+
+async fn load_own(user_id: UserId) -> Result<Vec<Playlist>> {
+    Ok(storage::read_json(&playlists_path(user_id))
+        .await?
+        .unwrap_or_default())
+}
+
+async fn save_own(user_id: UserId, playlists: &[Playlist]) -> Result<()> {
+    let path = playlists_path(user_id);
+    if playlists.is_empty() {
+        storage::remove(&path).await
+    } else {
+        storage::write_json(&path, &playlists).await
+    }
+}
+
+/// Playlists other users shared with the guild.
+async fn load_shared(guild_id: GuildId, except: UserId) -> Result<Vec<Playlist>> {
+    let mut dir = match tokio::fs::read_dir(playlists_dir()).await {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut shared = vec![];
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        let playlists: Vec<Playlist> = match storage::read_json(&path).await {
+            Ok(playlists) => playlists.unwrap_or_default(),
+            Err(e) => {
+                error!("Skipping unreadable playlists: {e:#}");
+                continue;
+            }
+        };
+        shared.extend(
+            playlists
+                .into_iter()
+                .filter(|p| p.shared_in == Some(guild_id.0) && p.owner_id != except.get()),
+        );
+    }
+
+    shared.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    Ok(shared)
+}
+
+/// The invoker's playlist called `name`, or else one shared with the guild.
+async fn find(ctx: Context<'_>, name: &str) -> Result<Playlist> {
+    let author = ctx.author().id;
+    let own = load_own(author).await?;
+    if let Some(playlist) = own.into_iter().find(|p| p.name.eq_ignore_ascii_case(name)) {
+        return Ok(playlist);
+    }
+
+    let guild_id = ctx.guild_id().unwrap();
+    match load_shared(guild_id.into(), author)
+        .await?
+        .into_iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+    {
+        Some(playlist) => Ok(playlist),
+        None => user_error!("There's no playlist called \"{name}\""),
+    }
+}
+
+/// Applies `f` to all of `user_id`'s playlists and saves them, unless it fails.
+async fn modify_all_own<T>(
+    user_id: UserId,
+    f: impl FnOnce(&mut Vec<Playlist>) -> Result<T>,
+) -> Result<T> {
+    let _guard = UPDATE_LOCK.lock().await;
+    let mut playlists = load_own(user_id).await?;
+    let result = f(&mut playlists)?;
+    save_own(user_id, &playlists).await?;
+    Ok(result)
+}
+
+/// Applies `f` to the invoker's own playlist called `name`.
+async fn modify_own<T>(
+    ctx: Context<'_>,
+    name: &str,
+    f: impl FnOnce(&mut Playlist) -> Result<T>,
+) -> Result<T> {
+    modify_all_own(ctx.author().id, |playlists| {
+        let Some(playlist) = playlists
+            .iter_mut()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+        else {
+            user_error!("You don't have a playlist called \"{name}\"")
+        };
+        f(playlist)
+    })
+    .await
+}
+
+/// Tracks for a search term or URL, without needing a player.
+async fn load_tracks(ctx: Context<'_>, term: &str) -> Result<Vec<TrackData>> {
+    let guild_id = ctx.guild_id().unwrap();
+    let query = if is_direct_query(term) {
+        term.to_string()
+    } else {
+        settings::get(guild_id)
+            .await
+            .default_search_engine()
+            .to_query(term)?
+    };
+
+    let loaded = ctx.data().lavalink.load_tracks(guild_id, &query).await?;
+    Ok(match raise_for_load_type(loaded)? {
+        Some(TrackLoadData::Track(track)) => vec![track],
+        Some(TrackLoadData::Search(results)) => results.into_iter().take(1).collect(),
+        Some(TrackLoadData::Playlist(playlist)) => playlist.tracks,
+        _ => vec![],
+    })
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LENGTH {
+        user_error!("Playlist names must be between 1 and {MAX_NAME_LENGTH} characters")
+    }
+    Ok(())
+}
+
+/// Save and play your own lists of tracks.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("save", "add", "remove", "list", "show", "delete", "share", "play"),
+    subcommand_required
+)]
+pub async fn playlist(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Save the current track and queue as a new playlist. Put `shared` in front of the name to share it right away.
+#[poise::command(slash_command, prefix_command)]
+async fn save(
+    ctx: Context<'_>,
+    #[description = "Let everyone in this server see and play it"]
+    #[flag]
+    shared: bool,
+    #[description = "Name of the new playlist"]
+    #[rest]
+    name: String,
+) -> Result<(), Error> {
+    let name = name.trim().to_string();
+    validate_name(&name)?;
+    let controller = PlayerController::from(check_if_in_channel(ctx).await?);
+
+    // Only what users requested, not e.g. the join announcement
+    let current = controller.ctx.get_player().await?.track;
+    let (queue, _) = controller.user_queue().await?;
+    let tracks: Vec<_> = current
+        .iter()
+        .chain(queue.iter().map(|t| &t.track))
+        .filter(|t| TrackUserData::try_from(*t).is_ok())
+        .map(PlaylistTrack::from)
+        .collect();
+    if tracks.is_empty() {
+        user_error!("There's nothing to save")
+    }
+
+    let author = ctx.author().id;
+    let playlist = Playlist {
+        name,
+        owner_id: author.get(),
+        shared_in: shared.then(|| ctx.guild_id().unwrap().get()),
+        tracks,
+    };
+    let summary = modify_all_own(author, |playlists| {
+        if playlists
+            .iter()
+            .any(|p| p.name.eq_ignore_ascii_case(&playlist.name))
+        {
+            user_error!("You already have a playlist called \"{}\"", playlist.name)
+        }
+        let summary = playlist.summary();
+        playlists.push(playlist);
+        Ok(summary)
+    })
+    .await?;
+    ctx.say(format!("Saved {summary}")).await?;
+
+    Ok(())
+}
+
+/// Add tracks to one of your playlists, creating it if needed.
+#[poise::command(slash_command, prefix_command)]
+async fn add(
+    ctx: Context<'_>,
+    #[description = "Playlist name"] name: String,
+    #[description = "Search term or URL"]
+    #[rest]
+    term: String,
+) -> Result<(), Error> {
+    let name = name.trim().to_string();
+    validate_name(&name)?;
+    ctx.defer().await?;
+
+    let tracks = load_tracks(ctx, &term).await?;
+    if tracks.is_empty() {
+        user_error!("No tracks found for \"{term}\"")
+    }
+
+    let author = ctx.author().id;
+    let summary = modify_all_own(author, |playlists| {
+        let index = match playlists
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(&name))
+        {
+            Some(index) => index,
+            None => {
+                playlists.push(Playlist {
+                    name,
+                    owner_id: author.get(),
+                    shared_in: None,
+                    tracks: vec![],
+                });
+                playlists.len() - 1
+            }
+        };
+        let playlist = &mut playlists[index];
+        playlist
+            .tracks
+            .extend(tracks.iter().map(PlaylistTrack::from));
+        Ok(playlist.summary())
+    })
+    .await?;
+
+    let added = match tracks.as_slice() {
+        [track] => format!("{} - {}", track.info.author, track.info.title),
+        tracks => format!("{} tracks", tracks.len()),
+    };
+    ctx.say(format!("Added {added} to {summary}")).await?;
+
+    Ok(())
+}
+
+/// Remove a track from one of your playlists.
+#[poise::command(slash_command, prefix_command)]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "Playlist name"] name: String,
+    #[description = "Position of the track, as shown by /playlist show"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), Error> {
+    let removed = modify_own(ctx, &name, |playlist| {
+        if position == 0 || position > playlist.tracks.len() {
+            user_error!("The playlist only has {} tracks", playlist.tracks.len())
+        }
+        Ok(playlist.tracks.remove(position - 1))
+    })
+    .await?;
+
+    ctx.say(format!("Removed {} - {}", removed.author, removed.title))
+        .await?;
+    Ok(())
+}
+
+/// List your playlists and the ones shared in this server.
+#[poise::command(slash_command, prefix_command)]
+async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let own = load_own(ctx.author().id).await?;
+    let shared = load_shared(ctx.guild_id().unwrap().into(), ctx.author().id).await?;
+
+    let describe = |playlists: &[Playlist]| {
+        if playlists.is_empty() {
+            "*None*".to_string()
+        } else {
+            playlists.iter().map(Playlist::summary).join("\n")
+        }
+    };
+    let embed = CreateEmbed::new()
+        .title("Playlists")
+        .field("Yours", describe(&own), false)
+        .field("Shared in this server", describe(&shared), false);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// Show the tracks of a playlist.
+#[poise::command(slash_command, prefix_command)]
+async fn show(
+    ctx: Context<'_>,
+    #[description = "Playlist name"]
+    #[rest]
+    name: String,
+) -> Result<(), Error> {
+    let playlist = find(ctx, &name).await?;
+
+    let lines: Vec<_> = playlist
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| t.line(i))
+        .collect();
+    let pages = messages::queue_pages(&playlist.summary(), &lines)
+        .into_iter()
+        .map(|page| page.title(&playlist.name))
+        .collect();
+    pagination::paginate(ctx, pages, 0).await?;
+
+    Ok(())
+}
+
+/// Delete one of your playlists.
+#[poise::command(slash_command, prefix_command)]
+async fn delete(
+    ctx: Context<'_>,
+    #[description = "Playlist name"]
+    #[rest]
+    name: String,
+) -> Result<(), Error> {
+    let playlist = modify_all_own(ctx.author().id, |playlists| {
+        let Some(index) = playlists
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(&name))
+        else {
+            user_error!("You don't have a playlist called \"{name}\"")
+        };
+        Ok(playlists.remove(index))
+    })
+    .await?;
+    ctx.say(format!("Deleted {}", playlist.name)).await?;
+
+    Ok(())
+}
+
+/// Share one of your playlists with this server, or stop sharing it.
+#[poise::command(slash_command, prefix_command)]
+async fn share(
+    ctx: Context<'_>,
+    #[description = "Playlist name"] name: String,
+    #[description = "Whether everyone in this server can see and play it"] shared: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap().get();
+    let name = modify_own(ctx, &name, |playlist| {
+        playlist.shared_in = shared.then_some(guild_id);
+        Ok(playlist.name.clone())
+    })
+    .await?;
+
+    if shared {
+        ctx.say(format!("Shared {name} with this server")).await?;
+    } else {
+        ctx.say(format!("{name} is private now")).await?;
+    }
+    Ok(())
+}
+
+/// Add a playlist to the queue.
+#[poise::command(slash_command, prefix_command)]
+async fn play(
+    ctx: Context<'_>,
+    #[description = "Playlist name"]
+    #[rest]
+    name: String,
+) -> Result<(), Error> {
+    let playlist = find(ctx, &name).await?;
+    if playlist.tracks.is_empty() {
+        user_error!("{} is empty", playlist.name)
+    }
+
+    let guild_id = ctx.guild_id().unwrap();
+    if ctx.data().lavalink.get_player_context(guild_id).is_some() {
+        permissions::require_same_channel(ctx).await?;
+    }
+    let controller = PlayerController::from(util::join(&ctx, guild_id, None).await?);
+
+    let encoded: Vec<_> = playlist.tracks.iter().map(|t| t.encoded.clone()).collect();
+    let tracks = ctx
+        .data()
+        .lavalink
+        .decode_tracks(guild_id, &encoded)
+        .await?;
+
+    ctx.say(format!("Added playlist to queue: {}", playlist.summary()))
+        .await?;
+    let user_data = TrackUserData::new(
+        ctx.author().id,
+        format!("playlist {}", playlist.name),
+        guild_id,
+    );
+    controller.enqueue_tracks(tracks, user_data).await?;
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use crate::permissions::{is_dj, require_dj, require_same_channel};
+use crate::player_controller::{LoopMode, PlayerController};
+use crate::util::TrackUserData;
+use crate::*;
+use itertools::Itertools;
+use lavalink_rs::prelude::TrackInQueue;
+use poise::serenity_prelude::UserId;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+
+/// How many tracks a report lists by name before summarizing the rest.
+const REPORT_LENGTH: usize = 10;
+
+/// What `/remove` should take out of the queue.
+#[derive(Debug, PartialEq)]
+enum Removal {
+    /// 1-based
+    Positions(BTreeSet<usize>),
+    Requester(UserId),
+}
+
+impl Removal {
+    /// Parses `3`, `3-7`, `1, 4 9-10` or `user:@someone`, validating positions against the queue length.
+    fn parse(spec: &str, count: usize) -> Result<Self> {
+        let spec = spec.trim();
+        if let Some(user) = spec.strip_prefix("user:") {
+            let id = user
+                .trim()
+                .trim_start_matches("<@")
+                .trim_start_matches('!')
+                .trim_end_matches('>')
+                .parse::<u64>()
+                .ok()
+                .filter(|&id| id != 0);
+            let Some(id) = id else {
+                user_error!("Mention the user, like user:@someone")
+            };
+            return Ok(Removal::Requester(UserId::new(id)));
+        }
+
+        let mut positions = BTreeSet::new();
+        for part in spec.split([',', ' ']).filter(|p| !p.is_empty()) {
+            let (start, end) = match part.split_once('-') {
+                Some((start, end)) => (parse_position(start, count)?, parse_position(end, count)?),
+                None => {
+                    let position = parse_position(part, count)?;
+                    (position, position)
+                }
+            };
+            if start > end {
+                user_error!("{part} is backwards, try {end}-{start}")
+            }
+            positions.extend(start..=end);
+        }
+
+        if positions.is_empty() {
+            user_error!("Name a position, a range like 3-7 or user:@someone")
+        }
+        Ok(Removal::Positions(positions))
+    }
+}
+
+/// A 1-based queue position, which must exist in a queue of `count` tracks.
+fn parse_position(position: &str, count: usize) -> Result<usize> {
+    let Ok(position) = position.trim().parse::<usize>() else {
+        user_error!("\"{position}\" isn't a queue position")
+    };
+    check_position(position, count)?;
+    Ok(position)
+}
+
+fn check_position(position: usize, count: usize) -> Result<()> {
+    if count == 0 {
+        user_error!("The queue is empty")
+    } else if position == 0 || position > count {
+        user_error!("Queue positions go from 1 to {count}")
+    }
+    Ok(())
+}
+
+fn track_name(track: &TrackInQueue) -> String {
+    format!("{} - {}", track.track.info.author, track.track.info.title)
+}
+
+/// Lists what was removed, by name for short lists.
+fn removal_report(removed: &[(usize, TrackInQueue)]) -> String {
+    let mut lines = removed
+        .iter()
+        .take(REPORT_LENGTH)
+        .map(|(position, track)| format!("{position} -> {}", track_name(track)))
+        .join("\n");
+    if removed.len() > REPORT_LENGTH {
+        lines.push_str(&format!("\n...and {} more", removed.len() - REPORT_LENGTH));
+    }
+
+    match removed {
+        [(_, track)] => format!("Removed {}", track_name(track)),
+        _ => format!("Removed {} tracks:\n{lines}", removed.len()),
+    }
+}
+
+/// Remove tracks from the queue by position, range (3-7), list (1, 4, 9) or requester (user:@someone).
+#[poise::command(slash_command, prefix_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Position, range like 3-7, list like 1,4,9 or user:@someone"]
+    #[rest]
+    tracks: String,
+) -> Result<(), Error> {
+    let controller = PlayerController::from(require_same_channel(ctx).await?);
+    let may_remove_others = is_dj(ctx).await?;
+    let author = ctx.author().id;
+
+    let removed = controller
+        .edit_user_queue(|queue| {
+            let removal = Removal::parse(&tracks, queue.len())?;
+            let is_removed = |position: usize, track: &TrackInQueue| match &removal {
+                Removal::Positions(positions) => positions.contains(&position),
+                Removal::Requester(user_id) => TrackUserData::try_from(&track.track)
+                    .is_ok_and(|data| data.requester_id.0 == user_id.get()),
+            };
+
+            let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(queue)
+                .into_iter()
+                .enumerate()
+                .map(|(i, track)| (i + 1, track))
+                .partition(|(position, track)| is_removed(*position, track));
+            if removed.is_empty() {
+                user_error!("There are no tracks to remove")
+            }
+
+            let only_own = removed.iter().all(|(_, track)| {
+                TrackUserData::try_from(&track.track)
+                    .is_ok_and(|data| data.requester_id.0 == author.get())
+            });
+            if !only_own && !may_remove_others {
+                user_error!("Only DJs can remove other people's tracks")
+            }
+
+            *queue = kept.into_iter().map(|(_, track)| track).collect();
+            Ok(removed)
+        })
+        .await?;
+    ctx.say(removal_report(&removed)).await?;
+
+    Ok(())
+}
+
+/// Move a track to another position in the queue.
+#[poise::command(slash_command, prefix_command, rename = "move")]
+pub async fn move_(
+    ctx: Context<'_>,
+    #[description = "Position of the track to move"]
+    #[min = 1]
+    from: usize,
+    #[description = "Position to move it to"]
+    #[min = 1]
+    to: usize,
+) -> Result<(), Error> {
+    let controller = PlayerController::from(require_dj(ctx).await?);
+    let name = controller
+        .edit_user_queue(|queue| {
+            check_position(from, queue.len())?;
+            check_position(to, queue.len())?;
+            if from == to {
+                user_error!("The track is already at position {to}")
+            }
+
+            let track = queue.remove(from - 1).unwrap();
+            let name = track_name(&track);
+            queue.insert(to - 1, track);
+            Ok(name)
+        })
+        .await?;
+
+    ctx.say(format!("Moved {name} from position {from} to {to}"))
+        .await?;
+    Ok(())
+}
+
+/// Skip to a position in the queue, dropping everything before it.
+#[poise::command(slash_command, prefix_command)]
+pub async fn skipto(
+    ctx: Context<'_>,
+    #[description = "Position of the track to play"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), Error> {
+    let player = require_dj(ctx).await?;
+    let controller = PlayerController::from(player);
+    let (target, skipped_count) = controller
+        .edit_user_queue(|queue| {
+            check_position(position, queue.len())?;
+
+            let skipped: VecDeque<_> = queue.drain(..position - 1).collect();
+            let skipped_count = skipped.len();
+            // Looping the queue means skipped tracks come around again
+            if controller.data.loop_mode() == LoopMode::Queue {
+                queue.extend(skipped);
+            }
+            Ok((track_name(&queue[0]), skipped_count))
+        })
+        .await?;
+
+    match controller.ctx.get_player().await?.track {
+        Some(current) => controller.skip_current(&current).await?,
+        None => controller.ctx.skip()?,
+    }
+
+    let skipped = match skipped_count {
+        0 => String::new(),
+        1 => " (skipped 1 queued track)".into(),
+        n => format!(" (skipped {n} queued tracks)"),
+    };
+    ctx.say(format!("Skipped to {target}{skipped}")).await?;
+    Ok(())
+}
+
+/// Remove repeated tracks from the queue, keeping the first of each.
+#[poise::command(slash_command, prefix_command)]
+pub async fn dedupe(ctx: Context<'_>) -> Result<(), Error> {
+    let controller = PlayerController::from(require_dj(ctx).await?);
+    let removed = controller
+        .edit_user_queue(|queue| {
+            let mut seen = HashSet::new();
+            let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(queue)
+                .into_iter()
+                .enumerate()
+                .map(|(i, track)| (i + 1, track))
+                .partition(|(_, track)| seen.insert(track.track.encoded.clone()));
+            if removed.is_empty() {
+                user_error!("There are no repeated tracks in the queue")
+            }
+
+            *queue = kept.into_iter().map(|(_, track)| track).collect();
+            Ok(removed)
+        })
+        .await?;
+    ctx.say(removal_report(&removed)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::queue_edit::Removal;
+    use poise::serenity_prelude::UserId;
+
+    fn positions(spec: &str, count: usize) -> Vec<usize> {
+        match Removal::parse(spec, count).unwrap() {
+            Removal::Positions(p) => p.into_iter().collect(),
+            Removal::Requester(_) => panic!("expected positions for {spec}"),
+        }
+    }
+
+    #[test]
+    fn parses_positions_ranges_and_lists() {
+        assert_eq!(positions("3", 5), [3]);
+        assert_eq!(positions("2-4", 5), [2, 3, 4]);
+        assert_eq!(positions("1, 5 2-3", 5), [1, 2, 3, 5]);
+        assert_eq!(positions("2-3,3-4", 5), [2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_positions() {
+        for spec in ["0", "6", "4-2", "1-6", "x", "", "-"] {
+            assert!(Removal::parse(spec, 5).is_err(), "{spec} should fail");
+        }
+        assert!(Removal::parse("1", 0).is_err());
+    }
+
+    #[test]
+    fn parses_requesters() {
+        let expected = Removal::Requester(UserId::new(1234));
+        for spec in ["user:<@1234>", "user:<@!1234>", "user:1234"] {
+            assert_eq!(Removal::parse(spec, 0).unwrap(), expected);
+        }
+        assert!(Removal::parse("user:someone", 0).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::player_controller::PlayerController;
+use crate::util::{TrackUserData, format_millis, source_to_emoji};
+use crate::*;
+use lavalink_rs::model::track::TrackData;
+use poise::CreateReply;
+use poise::serenity_prelude::{
+    ButtonStyle, ComponentInteractionDataKind, CreateActionRow, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption,
+};
+
+/// Results shown per engine at first, and added by each "More results".
+const RESULTS_STEP: usize = 3;
+/// Discord's limit for select menu options.
+const MAX_OPTIONS: usize = 25;
+/// The picker expires after this long without use.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Search results per engine, of which the first `shown` are offered.
+struct Picker {
+    results: Vec<Vec<TrackData>>,
+    shown: usize,
+}
+
+impl Picker {
+    fn visible(&self) -> Vec<Vec<TrackData>> {
+        self.results
+            .iter()
+            .map(|r| r.iter().take(self.shown).cloned().collect())
+            .collect()
+    }
+
+    /// Whether showing more results per engine would reveal any and still fit in the select menu.
+    fn can_show_more(&self) -> bool {
+        let next: usize = self
+            .results
+            .iter()
+            .map(|r| r.len().min(self.shown + RESULTS_STEP))
+            .sum();
+        let current: usize = self.results.iter().map(|r| r.len().min(self.shown)).sum();
+        next > current && next <= MAX_OPTIONS
+    }
+
+    fn components(&self, prefix: &str) -> Vec<CreateActionRow> {
+        let options: Vec<_> = self
+            .visible()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, track)| {
+                let label = format!("{}. {} - {}", i + 1, track.info.author, track.info.title);
+                CreateSelectMenuOption::new(truncate(&label, 100), i.to_string())
+                    .description(format!(
+                        "[{}] {}",
+                        format_millis(track.info.length),
+                        track.info.source_name
+                    ))
+                    .emoji(source_to_emoji(&track.info.source_name))
+            })
+            .collect();
+        let count = options.len() as u8;
+
+        let menu = CreateSelectMenu::new(
+            format!("{prefix}-pick"),
+            CreateSelectMenuKind::String { options },
+        )
+        .placeholder("Pick one or more tracks")
+        .min_values(1)
+        .max_values(count);
+        let buttons = vec![
+            CreateButton::new(format!("{prefix}-more"))
+                .label("More results")
+                .style(ButtonStyle::Secondary)
+                .disabled(!self.can_show_more()),
+            CreateButton::new(format!("{prefix}-cancel"))
+                .label("Cancel")
+                .style(ButtonStyle::Danger),
+        ];
+
+        vec![
+            CreateActionRow::SelectMenu(menu),
+            CreateActionRow::Buttons(buttons),
+        ]
+    }
+
+    /// The tracks behind the picked option values.
+    fn picked(&self, values: &[String]) -> Vec<TrackData> {
+        let visible: Vec<_> = self.visible().into_iter().flatten().collect();
+        values
+            .iter()
+            .filter_map(|v| v.parse::<usize>().ok())
+            .filter_map(|i| visible.get(i).cloned())
+            .collect()
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Search all preferred sources and pick tracks to queue.
+#[poise::command(slash_command, prefix_command)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "Search term"]
+    #[rest]
+    term: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if ctx.data().lavalink.get_player_context(guild_id).is_some() {
+        permissions::require_same_channel(ctx).await?;
+    }
+    let controller = PlayerController::from(util::join(&ctx, guild_id, None).await?);
+    ctx.defer().await?;
+
+    let results: Vec<Vec<TrackData>> = controller
+        .search_multiple(
+            &term,
+            &settings::get(guild_id).await.preferred_search_engines(),
+        )
+        .await
+        .into_iter()
+        .filter_map(|r| r.ok())
+        .filter(|r| !r.is_empty())
+        .collect();
+    if results.is_empty() {
+        user_error!("No results for \"{term}\"")
+    }
+
+    let mut picker = Picker {
+        results,
+        shown: RESULTS_STEP,
+    };
+    let prefix = ctx.id().to_string();
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .embed(messages::search_results(&picker.visible()))
+                .components(picker.components(&prefix)),
+        )
+        .await?;
+    let message = handle.message().await?.into_owned();
+
+    let picked = loop {
+        let Some(interaction) = message
+            .await_component_interaction(&ctx.serenity_context().shard)
+            .timeout(TIMEOUT)
+            .await
+        else {
+            handle
+                .edit(
+                    ctx,
+                    CreateReply::default()
+                        .content("Search expired")
+                        .components(vec![]),
+                )
+                .await?;
+            return Ok(());
+        };
+
+        if interaction.user.id != ctx.author().id {
+            let response = CreateInteractionResponseMessage::new()
+                .content("Only the person who searched can pick")
+                .ephemeral(true);
+            interaction
+                .create_response(ctx, CreateInteractionResponse::Message(response))
+                .await?;
+            continue;
+        }
+
+        let action = interaction
+            .data
+            .custom_id
+            .strip_prefix(&format!("{prefix}-"));
+        match (action, &interaction.data.kind) {
+            (Some("pick"), ComponentInteractionDataKind::StringSelect { values }) => {
+                interaction
+                    .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                    .await?;
+                break picker.picked(values);
+            }
+            (Some("more"), _) => {
+                if picker.can_show_more() {
+                    picker.shown += RESULTS_STEP;
+                }
+                let response = CreateInteractionResponseMessage::new()
+                    .embed(messages::search_results(&picker.visible()))
+                    .components(picker.components(&prefix));
+                interaction
+                    .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+                    .await?;
+            }
+            (Some("cancel"), _) => {
+                let response = CreateInteractionResponseMessage::new()
+                    .content("Search cancelled")
+                    .embeds(vec![])
+                    .components(vec![]);
+                interaction
+                    .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+                    .await?;
+                return Ok(());
+            }
+            _ => continue,
+        }
+    };
+
+    let reply = match picked.as_slice() {
+        [] => user_error!("None of the picked tracks are available anymore"),
+        [track] => CreateReply::default().embed(messages::added_to_queue(track)),
+        tracks => CreateReply::default().content(format!(
+            "Added {} tracks to queue:\n{}",
+            tracks.len(),
+            tracks
+                .iter()
+                .map(|t| format!("- {} - {}", t.info.author, t.info.title))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )),
+    };
+    handle.edit(ctx, reply.components(vec![])).await?;
+
+    let user_data = TrackUserData::new(ctx.author().id, term, guild_id);
+    controller.enqueue_tracks(picked, user_data).await?;
+
+    Ok(())
+}
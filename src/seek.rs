@@ -0,0 +1,246 @@
+use crate::permissions::{require_dj_or_requester, require_same_channel};
+use crate::util::format_millis;
+use crate::*;
+use chrono::Utc;
+use lavalink_rs::model::player::Player;
+
+/// Used by `forward` and `rewind` without a time.
+const DEFAULT_JUMP: Duration = Duration::from_secs(10);
+
+/// Where `/seek` should go, parsed from e.g. `1:23`, `+30` or `-15`.
+#[derive(Debug, PartialEq)]
+pub enum SeekTarget {
+    Absolute(Duration),
+    Forward(Duration),
+    Backward(Duration),
+}
+
+impl SeekTarget {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if let Some(rest) = text.strip_prefix('+') {
+            parse_duration(rest).map(SeekTarget::Forward)
+        } else if let Some(rest) = text.strip_prefix('-') {
+            parse_duration(rest).map(SeekTarget::Backward)
+        } else {
+            parse_duration(text).map(SeekTarget::Absolute)
+        }
+    }
+
+    /// The position to jump to from `position`, in milliseconds.
+    fn resolve(&self, position: u64) -> u64 {
+        match self {
+            SeekTarget::Absolute(d) => d.as_millis() as u64,
+            SeekTarget::Forward(d) => position.saturating_add(d.as_millis() as u64),
+            SeekTarget::Backward(d) => position.saturating_sub(d.as_millis() as u64),
+        }
+    }
+}
+
+/// Parses `90`, `90s`, `1m30s`, `1h`, `1:23` or `1:02:03`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return None;
+    }
+
+    if text.contains(':') {
+        let parts: Vec<u64> = text
+            .split(':')
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?;
+        let (hours, minutes, seconds) = match parts[..] {
+            [m, s] => (0, m, s),
+            [h, m, s] if m < 60 => (h, m, s),
+            _ => return None,
+        };
+        if seconds >= 60 {
+            return None;
+        }
+        let total = hours
+            .checked_mul(3600)?
+            .checked_add(minutes.checked_mul(60)?)?
+            .checked_add(seconds)?;
+        return from_secs(total);
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    // A trailing number without unit is in seconds, like in `1m30`
+    if !number.is_empty() {
+        total = total.checked_add(number.parse::<u64>().ok()?)?;
+    }
+
+    from_secs(total)
+}
+
+/// Positions are handled in milliseconds, so durations that don't fit in those aren't valid.
+fn from_secs(secs: u64) -> Option<Duration> {
+    secs.checked_mul(1000)?;
+    Some(Duration::from_secs(secs))
+}
+
+/// The player's position right now, accounting for time since the last player update.
+pub fn current_position(player: &Player) -> u64 {
+    if player.paused {
+        return player.state.position;
+    }
+    let elapsed = (Utc::now().timestamp_millis() as u64).saturating_sub(player.state.time);
+    player.state.position + elapsed
+}
+
+/// Jumps within the current track, if the invoker may and the track allows it.
+async fn seek_to(ctx: Context<'_>, target: SeekTarget) -> Result<()> {
+    let player_ctx = require_same_channel(ctx).await?;
+    let player = player_ctx.get_player().await?;
+    let Some(track) = &player.track else {
+        user_error!("Nothing is playing")
+    };
+    require_dj_or_requester(ctx, track).await?;
+
+    let info = &track.info;
+    if info.is_stream {
+        user_error!("Can't seek in live streams")
+    } else if !info.is_seekable {
+        user_error!("This track doesn't support seeking")
+    }
+
+    let position = target.resolve(current_position(&player).min(info.length));
+    if position > info.length {
+        user_error!(
+            "That's past the end of the track, it's only {} long",
+            format_millis(info.length)
+        )
+    }
+
+    player_ctx
+        .set_position(Duration::from_millis(position))
+        .await?;
+    ctx.say(format!(
+        "Jumped to {} / {}",
+        format_millis(position),
+        format_millis(info.length)
+    ))
+    .await?;
+    Ok(())
+}
+
+fn parse_or_complain(time: &str) -> Result<Duration> {
+    match parse_duration(time) {
+        Some(duration) => Ok(duration),
+        None => user_error!("\"{time}\" isn't a time, try 30, 90s or 1:30"),
+    }
+}
+
+/// Jump to a time in the current track, like 1:23, 90s, +30 or -15.
+#[poise::command(slash_command, prefix_command)]
+pub async fn seek(
+    ctx: Context<'_>,
+    #[description = "Time like 1:23, 1:02:03 or 90s, or +30/-15 to jump relatively"] time: String,
+) -> Result<(), Error> {
+    let Some(target) = SeekTarget::parse(&time) else {
+        user_error!("\"{time}\" isn't a time, try 1:23, 90s, +30 or -15")
+    };
+    seek_to(ctx, target).await
+}
+
+/// Skip ahead in the current track.
+#[poise::command(slash_command, prefix_command)]
+pub async fn forward(
+    ctx: Context<'_>,
+    #[description = "How far, like 30 or 1:30 (default 10s)"] time: Option<String>,
+) -> Result<(), Error> {
+    let duration = match time {
+        Some(time) => parse_or_complain(&time)?,
+        None => DEFAULT_JUMP,
+    };
+    seek_to(ctx, SeekTarget::Forward(duration)).await
+}
+
+/// Go back in the current track.
+#[poise::command(slash_command, prefix_command)]
+pub async fn rewind(
+    ctx: Context<'_>,
+    #[description = "How far, like 30 or 1:30 (default 10s)"] time: Option<String>,
+) -> Result<(), Error> {
+    let duration = match time {
+        Some(time) => parse_or_complain(&time)?,
+        None => DEFAULT_JUMP,
+    };
+    seek_to(ctx, SeekTarget::Backward(duration)).await
+}
+
+/// Play the current track from the start.
+#[poise::command(slash_command, prefix_command)]
+pub async fn replay(ctx: Context<'_>) -> Result<(), Error> {
+    seek_to(ctx, SeekTarget::Absolute(Duration::ZERO)).await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::seek::{SeekTarget, parse_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations() {
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(parse_duration("90"), secs(90));
+        assert_eq!(parse_duration("90s"), secs(90));
+        assert_eq!(parse_duration("1:23"), secs(83));
+        assert_eq!(parse_duration("1:02:03"), secs(3723));
+        assert_eq!(parse_duration("1m30s"), secs(90));
+        assert_eq!(parse_duration("1m30"), secs(90));
+        assert_eq!(parse_duration("2H"), secs(7200));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for text in ["", "abc", "1:60", "1:60:00", "1:2:3:4", ":30", "1x", "1.5"] {
+            assert_eq!(parse_duration(text), None, "{text} should fail");
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        let huge = [
+            "99999999999999999h",
+            "5124095576030431h",
+            "18446744073709551615m",
+            "18446744073709551615s",
+            "18446744073709551615h1s",
+            "99999999999999999:00",
+            "99999999999999999:00:00",
+        ];
+        for text in huge {
+            assert_eq!(parse_duration(text), None, "{text} should fail");
+        }
+    }
+
+    #[test]
+    fn parses_relative_targets() {
+        let d = Duration::from_secs;
+        assert_eq!(SeekTarget::parse("+30"), Some(SeekTarget::Forward(d(30))));
+        assert_eq!(SeekTarget::parse("-15"), Some(SeekTarget::Backward(d(15))));
+        assert_eq!(
+            SeekTarget::parse("-1:00"),
+            Some(SeekTarget::Backward(d(60)))
+        );
+        assert_eq!(SeekTarget::parse("45"), Some(SeekTarget::Absolute(d(45))));
+        assert_eq!(SeekTarget::Backward(d(15)).resolve(10_000), 0);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::player_controller::{LoopMode, PlayerController};
+use crate::storage;
+use crate::util::{TrackUserData, get_own_voice_channel};
+use crate::*;
+use lavalink_rs::model::track::TrackData;
+use lavalink_rs::prelude::TrackInQueue;
+use parking_lot::Mutex;
+use poise::serenity_prelude::ChannelId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/// Player updates only move the position, so [`save_periodically`] writes at most this often for them.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Held while resuming, so nodes getting ready at the same time don't resume the same session twice.
+static RESUME_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// When each guild's session was last written, and what it looked like apart from the position.
+static LAST_SAVES: LazyLock<Mutex<HashMap<GuildId, (Instant, serde_json::Value)>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedTrack {
+    pub encoded: String,
+    pub user_data: TrackUserData,
+}
+
+impl TryFrom<&TrackData> for SavedTrack {
+    type Error = Error;
+
+    fn try_from(track: &TrackData) -> std::result::Result<SavedTrack, Error> {
+        Ok(Self {
+            encoded: track.encoded.clone(),
+            user_data: TrackUserData::try_from(track)?,
+        })
+    }
+}
+
+/// Everything needed to pick a guild's playback back up after a restart.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub guild_id: GuildId,
+    pub voice_channel: ChannelId,
+    pub text_channel: ChannelId,
+    pub current: Option<SavedTrack>,
+    /// Position in `current`, in milliseconds
+    pub position: u64,
+    pub paused: bool,
+    pub queue: Vec<SavedTrack>,
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+    /// Paused because nobody was listening, rather than by someone
+    #[serde(default)]
+    pub auto_paused: bool,
+}
+
+impl Session {
+    pub async fn capture(controller: &PlayerController) -> Result<Self> {
+        let data = &controller.data;
+        let player = controller.ctx.get_player().await?;
+        let voice_channel = get_own_voice_channel(&data.cache, data.guild_id.0)?.id;
+
+        // Tracks without user data (e.g. the join announcement) aren't worth resuming. The loop copy comes back on its
+        // own once the current track starts again.
+        let (queue, _) = controller.user_queue().await?;
+        let queue = queue
+            .iter()
+            .filter_map(|t| SavedTrack::try_from(&t.track).ok())
+            .collect();
+        let current = player
+            .track
+            .as_ref()
+            .and_then(|t| SavedTrack::try_from(t).ok());
+
+        Ok(Self {
+            guild_id: data.guild_id,
+            voice_channel,
+            text_channel: data.text_channel,
+            current,
+            position: player.state.position,
+            paused: player.paused,
+            queue,
+            loop_mode: data.loop_mode(),
+            auto_paused: data.auto_paused.load(Ordering::SeqCst),
+        })
+    }
+}
+
+fn sessions_dir() -> PathBuf {
+    storage::data_dir().join("sessions")
+}
+
+fn session_path(guild_id: GuildId) -> PathBuf {
+    sessions_dir().join(format!("{}.json", guild_id.0))
+}
+
+pub async fn save(controller: &PlayerController) -> Result<()> {
+    let session = Session::capture(controller).await?;
+    write(&session).await
+}
+
+/// Like [`save`], but skips the write if only the position changed since the last one, unless that was a while ago.
+pub async fn save_periodically(controller: &PlayerController) -> Result<()> {
+    let session = Session::capture(controller).await?;
+    let is_due = match LAST_SAVES.lock().get(&session.guild_id) {
+        Some((at, state)) => at.elapsed() >= SAVE_INTERVAL || *state != state_of(&session)?,
+        None => true,
+    };
+    if is_due {
+        write(&session).await?;
+    }
+    Ok(())
+}
+
+/// The session without its position, to tell whether anything else changed.
+fn state_of(session: &Session) -> Result<serde_json::Value> {
+    let mut state = serde_json::to_value(session)?;
+    state["position"] = serde_json::Value::Null;
+    Ok(state)
+}
+
+async fn write(session: &Session) -> Result<()> {
+    storage::write_json(&session_path(session.guild_id), session).await?;
+    LAST_SAVES
+        .lock()
+        .insert(session.guild_id, (Instant::now(), state_of(session)?));
+    Ok(())
+}
+
+pub async fn forget(guild_id: impl Into<GuildId>) -> Result<()> {
+    let guild_id = guild_id.into();
+    LAST_SAVES.lock().remove(&guild_id);
+    storage::remove(&session_path(guild_id)).await
+}
+
+/// Rejoins and resumes every saved session without a player. Sessions that fail to resume are logged and forgotten.
+pub async fn resume_all(lavalink: &LavalinkClient, ctx: &serenity::Context) -> Result<()> {
+    let _lock = RESUME_LOCK.lock().await;
+    let mut dir = match tokio::fs::read_dir(sessions_dir()).await {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        let session: Session = match storage::read_json(&path).await {
+            Ok(Some(session)) => session,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Skipping unreadable session: {e:#}");
+                continue;
+            }
+        };
+
+        let guild_id = session.guild_id;
+        if lavalink.get_player_context(guild_id).is_some() {
+            continue; // Still playing, e.g. because only one of several nodes reconnected
+        }
+
+        if let Err(e) = resume(lavalink, ctx, session).await {
+            error!("Failed to resume session for guild {}: {e:#}", guild_id.0);
+            if let Err(e) = forget(guild_id).await {
+                error!("Failed to forget session for guild {}: {e:#}", guild_id.0);
+            }
+        } else {
+            info!("Resumed session for guild {}", guild_id.0);
+        }
+    }
+
+    Ok(())
+}
+
+async fn resume(
+    lavalink: &LavalinkClient,
+    ctx: &serenity::Context,
+    session: Session,
+) -> Result<()> {
+    let songbird = songbird::get(ctx)
+        .await
+        .with_context(|| "Songbird isn't registered")?;
+    let controller = PlayerController::init(
+        lavalink,
+        ctx,
+        songbird,
+        session.guild_id,
+        session.voice_channel,
+        session.text_channel,
+    )
+    .await?;
+    *controller.data.loop_mode.lock() = session.loop_mode;
+    // So the watchdog resumes once someone is back, like before the restart
+    controller
+        .data
+        .auto_paused
+        .store(session.auto_paused, Ordering::SeqCst);
+
+    let saved: Vec<_> = session.current.iter().chain(&session.queue).collect();
+    if saved.is_empty() {
+        return Ok(());
+    }
+
+    let encoded: Vec<_> = saved.iter().map(|t| t.encoded.clone()).collect();
+    let decoded = lavalink.decode_tracks(session.guild_id, &encoded).await?;
+
+    let mut queue = decoded
+        .into_iter()
+        .zip(saved)
+        .map(|(mut track, saved)| {
+            track.user_data = Some(serde_json::to_value(&saved.user_data)?);
+            Ok(track)
+        })
+        .collect::<Result<VecDeque<_>>>()?;
+    let current = session.current.as_ref().and_then(|_| queue.pop_front());
+
+    controller
+        .restore_playback(
+            queue.into_iter().map(TrackInQueue::from).collect(),
+            current,
+            session.position,
+            session.paused,
+        )
+        .await
+}
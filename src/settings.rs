@@ -0,0 +1,449 @@
+use crate::config::{config, parse_search_engine, validate_join_sound};
+use crate::player_controller::PlayerController;
+use crate::storage;
+use crate::*;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use poise::CreateReply;
+use poise::serenity_prelude::{CreateEmbed, Mentionable, Role, RoleId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+
+static CACHE: LazyLock<Mutex<HashMap<GuildId, Arc<GuildSettings>>>> =
+    LazyLock::new(Default::default);
+/// Held by [`update`] from reading the settings until they're saved, so concurrent changes don't overwrite each other.
+static UPDATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Per-guild overrides of the global configuration. `None` means "use the global default".
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub prefix: Option<String>,
+    pub alone_timeout_secs: Option<u64>,
+    /// Empty to disable
+    pub join_sound: Option<String>,
+    pub default_engine: Option<String>,
+    pub preferred_engines: Option<Vec<String>>,
+    /// Without one, only admins and whoever is alone with the bot count as DJs
+    pub dj_role: Option<RoleId>,
+    pub skip_vote_percent: Option<u8>,
+    pub fair_queue: Option<bool>,
+    pub autoplay: Option<bool>,
+}
+
+impl GuildSettings {
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(&config().discord.prefix)
+    }
+
+    pub fn alone_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.alone_timeout_secs
+                .unwrap_or(config().player.alone_timeout_secs),
+        )
+    }
+
+    pub fn join_sound(&self) -> Option<&str> {
+        match &self.join_sound {
+            Some(sound) => Some(sound.as_str()).filter(|s| !s.is_empty()),
+            None => config().join_sound(),
+        }
+    }
+
+    pub fn skip_vote_percent(&self) -> u8 {
+        self.skip_vote_percent
+            .unwrap_or(config().player.skip_vote_percent)
+    }
+
+    pub fn fair_queue(&self) -> bool {
+        self.fair_queue.unwrap_or(config().player.fair_queue)
+    }
+
+    pub fn autoplay(&self) -> bool {
+        self.autoplay.unwrap_or(config().player.autoplay)
+    }
+
+    pub fn default_search_engine(&self) -> SearchEngines {
+        self.default_engine
+            .as_deref()
+            .and_then(|e| parse_search_engine(e).ok())
+            .unwrap_or_else(|| config().default_search_engine())
+    }
+
+    pub fn preferred_search_engines(&self) -> Vec<SearchEngines> {
+        match &self.preferred_engines {
+            Some(engines) => engines
+                .iter()
+                .filter_map(|e| parse_search_engine(e).ok())
+                .collect(),
+            None => config().preferred_search_engines(),
+        }
+    }
+}
+
+fn settings_path(guild_id: GuildId) -> PathBuf {
+    storage::data_dir()
+        .join("settings")
+        .join(format!("{}.json", guild_id.0))
+}
+
+/// Settings for a guild, loaded from disk on first access. Falls back to the defaults if they can't be read.
+pub async fn get(guild_id: impl Into<GuildId>) -> Arc<GuildSettings> {
+    let guild_id = guild_id.into();
+    if let Some(settings) = CACHE.lock().get(&guild_id) {
+        return settings.clone();
+    }
+
+    let settings = match storage::read_json(&settings_path(guild_id)).await {
+        Ok(settings) => settings.unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to load settings for guild {}: {e:#}", guild_id.0);
+            GuildSettings::default()
+        }
+    };
+    CACHE
+        .lock()
+        .entry(guild_id)
+        .or_insert_with(|| Arc::new(settings))
+        .clone()
+}
+
+pub async fn update(
+    guild_id: impl Into<GuildId>,
+    f: impl FnOnce(&mut GuildSettings),
+) -> Result<Arc<GuildSettings>> {
+    let guild_id = guild_id.into();
+    let _guard = UPDATE_LOCK.lock().await;
+    let mut settings = (*get(guild_id).await).clone();
+    f(&mut settings);
+
+    storage::write_json(&settings_path(guild_id), &settings).await?;
+    let settings = Arc::new(settings);
+    CACHE.lock().insert(guild_id, settings.clone());
+    Ok(settings)
+}
+
+fn settings_embed(settings: &GuildSettings) -> CreateEmbed {
+    let or_default = |set: bool| if set { "" } else { " *(default)*" };
+
+    let join_sound = match settings.join_sound() {
+        Some(sound) => format!("`{sound}`"),
+        None => "disabled".into(),
+    };
+    let engines = settings
+        .preferred_engines
+        .clone()
+        .unwrap_or_else(|| config().search.preferred_engines.clone())
+        .join(", ");
+    let queue_order = if settings.fair_queue() {
+        "requesters take turns"
+    } else {
+        "first come, first served"
+    };
+    let default_engine = settings
+        .default_engine
+        .as_deref()
+        .unwrap_or(&config().search.default_engine);
+
+    CreateEmbed::new()
+        .title("Settings")
+        .field(
+            "Prefix",
+            format!(
+                "`{}`{}",
+                settings.prefix(),
+                or_default(settings.prefix.is_some())
+            ),
+            true,
+        )
+        .field(
+            "Leave without listeners after",
+            format!(
+                "{} minutes{}",
+                settings.alone_timeout().as_secs() / 60,
+                or_default(settings.alone_timeout_secs.is_some())
+            ),
+            true,
+        )
+        .field(
+            "Votes to skip",
+            format!(
+                "{}% of listeners{}",
+                settings.skip_vote_percent(),
+                or_default(settings.skip_vote_percent.is_some())
+            ),
+            true,
+        )
+        .field(
+            "Queue order",
+            format!("{queue_order}{}", or_default(settings.fair_queue.is_some())),
+            true,
+        )
+        .field(
+            "Autoplay",
+            format!(
+                "{}{}",
+                if settings.autoplay() { "on" } else { "off" },
+                or_default(settings.autoplay.is_some())
+            ),
+            true,
+        )
+        .field(
+            "Join sound",
+            format!("{join_sound}{}", or_default(settings.join_sound.is_some())),
+            false,
+        )
+        .field(
+            "Search engine",
+            format!(
+                "{default_engine}{}",
+                or_default(settings.default_engine.is_some())
+            ),
+            true,
+        )
+        .field(
+            "Source order",
+            format!(
+                "{engines}{}",
+                or_default(settings.preferred_engines.is_some())
+            ),
+            true,
+        )
+        .field(
+            "DJ role",
+            match settings.dj_role {
+                Some(role) => role.mention().to_string(),
+                None => "none, only admins can control playback for everyone".into(),
+            },
+            false,
+        )
+}
+
+/// Change how the bot behaves in this server.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands(
+        "show",
+        "prefix",
+        "alone_timeout",
+        "skip_votes",
+        "fair_queue",
+        "join_sound",
+        "search_engine",
+        "sources",
+        "dj_role",
+        "reset"
+    ),
+    subcommand_required,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn settings(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show the current settings.
+#[poise::command(slash_command, prefix_command)]
+async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    let settings = get(ctx.guild_id().unwrap()).await;
+    ctx.send(CreateReply::default().embed(settings_embed(&settings)))
+        .await?;
+    Ok(())
+}
+
+/// Set the prefix for text commands.
+#[poise::command(slash_command, prefix_command)]
+async fn prefix(
+    ctx: Context<'_>,
+    #[description = "New prefix"] prefix: String,
+) -> Result<(), Error> {
+    if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+        user_error!("The prefix can't be empty or contain spaces")
+    }
+
+    update(ctx.guild_id().unwrap(), |s| s.prefix = Some(prefix.clone())).await?;
+    ctx.say(format!("Prefix set to `{prefix}`")).await?;
+    Ok(())
+}
+
+/// Set how long the bot stays paused in a voice channel without listeners before leaving.
+#[poise::command(slash_command, prefix_command, rename = "alone-timeout")]
+async fn alone_timeout(
+    ctx: Context<'_>,
+    #[description = "Minutes"]
+    #[min = 1]
+    #[max = 1440]
+    minutes: u64,
+) -> Result<(), Error> {
+    if !(1..=1440).contains(&minutes) {
+        user_error!("The timeout must be between 1 and 1440 minutes")
+    }
+
+    update(ctx.guild_id().unwrap(), |s| {
+        s.alone_timeout_secs = Some(minutes * 60)
+    })
+    .await?;
+    ctx.say(format!("Leaving after {minutes} minutes without listeners"))
+        .await?;
+    Ok(())
+}
+
+/// Set the share of listeners needed to skip someone else's track.
+#[poise::command(slash_command, prefix_command, rename = "skip-votes")]
+async fn skip_votes(
+    ctx: Context<'_>,
+    #[description = "Percent of listeners"]
+    #[min = 1]
+    #[max = 100]
+    percent: u8,
+) -> Result<(), Error> {
+    if !(1..=100).contains(&percent) {
+        user_error!("The share must be between 1 and 100 percent")
+    }
+
+    update(ctx.guild_id().unwrap(), |s| {
+        s.skip_vote_percent = Some(percent)
+    })
+    .await?;
+    ctx.say(format!("Skipping needs votes from {percent}% of listeners"))
+        .await?;
+    Ok(())
+}
+
+/// Let requesters take turns in the queue, or play tracks in the order they were queued.
+#[poise::command(slash_command, prefix_command, rename = "fair-queue")]
+async fn fair_queue(
+    ctx: Context<'_>,
+    #[description = "Whether requesters take turns"] enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    update(guild_id, |s| s.fair_queue = Some(enabled)).await?;
+
+    if let (true, Some(player)) = (enabled, ctx.data().lavalink.get_player_context(guild_id)) {
+        PlayerController::from(player).apply_fair_order().await?;
+    }
+
+    if enabled {
+        ctx.say("Requesters take turns in the queue now").await?;
+    } else {
+        ctx.say("New tracks go to the end of the queue now").await?;
+    }
+    Ok(())
+}
+
+/// Set the sound played when joining a channel.
+#[poise::command(slash_command, prefix_command, rename = "join-sound")]
+async fn join_sound(
+    ctx: Context<'_>,
+    #[description = "URL, file:<name> for a bundled sound or one from the sounds directory, or \"none\" to disable"]
+    sound: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let sound = if sound.eq_ignore_ascii_case("none") {
+        String::new()
+    } else {
+        sound.trim().to_string()
+    };
+    if let Err(e) = validate_join_sound(&sound) {
+        user_error!("{e}")
+    }
+    if sound.starts_with("file:") {
+        let identifier = config().sound_identifier(&sound);
+        let loaded = ctx
+            .data()
+            .lavalink
+            .load_tracks(guild_id, &identifier)
+            .await?;
+        if let Err(e) = util::single_sound(loaded) {
+            user_error!("Lavalink can't play {sound}: {e}")
+        }
+    }
+
+    let settings = update(guild_id, |s| s.join_sound = Some(sound)).await?;
+    match settings.join_sound() {
+        Some(sound) => ctx.say(format!("Join sound set to `{sound}`")).await?,
+        None => ctx.say("Join sound disabled").await?,
+    };
+    Ok(())
+}
+
+/// Set the search engine used by play.
+#[poise::command(slash_command, prefix_command, rename = "search-engine")]
+async fn search_engine(
+    ctx: Context<'_>,
+    #[description = "e.g. youtube, youtube_music, soundcloud, deezer, spotify"] engine: String,
+) -> Result<(), Error> {
+    let engine = engine.trim().to_lowercase();
+    if let Err(e) = parse_search_engine(&engine) {
+        user_error!("{e}")
+    }
+
+    update(ctx.guild_id().unwrap(), |s| {
+        s.default_engine = Some(engine.clone())
+    })
+    .await?;
+    ctx.say(format!("Searching {engine} by default")).await?;
+    Ok(())
+}
+
+/// Set which sources search and track recovery use, in order of preference.
+#[poise::command(slash_command, prefix_command)]
+async fn sources(
+    ctx: Context<'_>,
+    #[description = "Comma-separated, e.g. youtube,deezer,soundcloud"]
+    #[rest]
+    engines: String,
+) -> Result<(), Error> {
+    let engines: Vec<String> = engines
+        .split(',')
+        .map(|e| e.trim().to_lowercase())
+        .filter(|e| !e.is_empty())
+        .unique()
+        .collect();
+    if engines.is_empty() {
+        user_error!("Name at least one source")
+    }
+    for engine in &engines {
+        if let Err(e) = parse_search_engine(engine) {
+            user_error!("{e}")
+        }
+    }
+
+    let joined = engines.join(", ");
+    update(ctx.guild_id().unwrap(), |s| {
+        s.preferred_engines = Some(engines)
+    })
+    .await?;
+    ctx.say(format!("Source order set to {joined}")).await?;
+    Ok(())
+}
+
+/// Set the role allowed to control playback for everyone, or remove it.
+#[poise::command(slash_command, prefix_command, rename = "dj-role")]
+async fn dj_role(
+    ctx: Context<'_>,
+    #[description = "Leave empty to leave it to admins"] role: Option<Role>,
+) -> Result<(), Error> {
+    let role_id = role.as_ref().map(|r| r.id);
+    update(ctx.guild_id().unwrap(), |s| s.dj_role = role_id).await?;
+    match role {
+        Some(role) => ctx.say(format!("DJ role set to {}", role.name)).await?,
+        None => {
+            ctx.say("DJ role removed, only admins can control playback for everyone")
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// Reset all settings to their defaults.
+#[poise::command(slash_command, prefix_command)]
+async fn reset(ctx: Context<'_>) -> Result<(), Error> {
+    update(ctx.guild_id().unwrap(), |s| *s = GuildSettings::default()).await?;
+    ctx.say("Settings reset to their defaults").await?;
+    Ok(())
+}
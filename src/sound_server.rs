@@ -0,0 +1,152 @@
+use crate::config::{config, is_sound_name};
+use crate::*;
+use poise_error::anyhow::bail;
+use std::borrow::Cow;
+use std::io::ErrorKind;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Sounds that ship with the bot, so `file:` works without setting up a sounds directory.
+const BUNDLED: &[(&str, &[u8])] = &[("join.wav", include_bytes!("../sounds/join.wav"))];
+
+/// Serves `file:` sounds over HTTP, which is how Lavalink loads them. Does nothing if no address is configured.
+pub async fn start() -> Result<()> {
+    let address = &config().player.sound_server_listen;
+    if address.is_empty() {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to listen on {address} for the sound server"))?;
+    info!("Serving sounds on {address}");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            warn!("Failed to serve a sound: {e:#}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept a sound server connection: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    let mut head = vec![];
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > 8 * 1024 {
+            bail!("Request head too long");
+        }
+        match stream.read(&mut buf).await? {
+            0 => return Ok(()),
+            n => head.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let Some((method, name)) = parse_request(&head) else {
+        return reply(stream, "400 Bad Request", "text/plain", b"", true).await;
+    };
+    let Some(sound) = sound(name).await? else {
+        return reply(stream, "404 Not Found", "text/plain", b"", true).await;
+    };
+    reply(
+        stream,
+        "200 OK",
+        content_type(name),
+        &sound,
+        method != "HEAD",
+    )
+    .await
+}
+
+async fn reply(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    send_body: bool,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if send_body {
+        stream.write_all(body).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// The method and sound name of a `GET /<name>` or `HEAD /<name>` request.
+fn parse_request(head: &str) -> Option<(&str, &str)> {
+    let mut parts = head.lines().next()?.split(' ');
+    let method = parts.next().filter(|m| matches!(*m, "GET" | "HEAD"))?;
+    let name = parts.next()?.strip_prefix('/')?;
+    is_sound_name(name).then_some((method, name))
+}
+
+/// A bundled sound, or one from `sounds_dir`.
+async fn sound(name: &str) -> Result<Option<Cow<'static, [u8]>>> {
+    if let Some((_, bytes)) = BUNDLED.iter().find(|(n, _)| *n == name) {
+        return Ok(Some(Cow::Borrowed(*bytes)));
+    }
+    let path = config().player.sounds_dir.join(name);
+    match tokio::fs::read(&path).await {
+        Ok(bytes) => Ok(Some(Cow::Owned(bytes))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .as_deref()
+    {
+        Some("wav") => "audio/wav",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg" | "opus") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        Some("m4a") => "audio/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sound_server::{BUNDLED, parse_request};
+
+    #[test]
+    fn parses_sound_requests() {
+        let head = "GET /join.wav HTTP/1.1\r\nHost: smoltunes:2334\r\n\r\n";
+        assert_eq!(parse_request(head), Some(("GET", "join.wav")));
+        assert_eq!(
+            parse_request("HEAD /join.wav HTTP/1.1\r\n\r\n"),
+            Some(("HEAD", "join.wav"))
+        );
+
+        assert_eq!(parse_request("POST /join.wav HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(parse_request("GET /../config.toml HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(parse_request("GET /a/b.mp3 HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(parse_request("GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn bundles_the_join_sound() {
+        let (_, wav) = BUNDLED
+            .iter()
+            .find(|(name, _)| *name == "join.wav")
+            .unwrap();
+        assert!(wav.starts_with(b"RIFF"));
+    }
+}
@@ -1,19 +1,23 @@
-use crate::player_controller::PlayerData;
-use crate::util::{format_millis, source_to_color, source_to_emoji, TrackUserData};
+use crate::player_controller::{LoopMode, PlayerController, PlayerData};
+use crate::util::{TrackUserData, format_millis, progress_bar, source_to_color, source_to_emoji};
 use crate::*;
-use futures::StreamExt;
 use lavalink_rs::model::track::{TrackData, TrackInfo};
-use lavalink_rs::player_context::QueueRef;
-use lavalink_rs::prelude::PlayerContext;
+use lavalink_rs::prelude::{PlayerContext, TrackInQueue};
+use poise::ChoiceParameter;
 use poise::serenity_prelude::{CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, Member};
+use std::collections::VecDeque;
 use std::sync::Arc;
 
 pub struct StatusBuilder {
     cache: Arc<SerenityCache>,
     current_track: Option<TrackData>,
     current_position: u64,
-    queue: QueueRef,
+    paused: bool,
+    queue: VecDeque<TrackInQueue>,
     current_member: Member,
+    loop_mode: LoopMode,
+    volume: u16,
+    filters: Vec<String>,
 }
 
 impl StatusBuilder {
@@ -24,21 +28,32 @@ impl StatusBuilder {
         let guild = data.cache.guild(player.guild_id.0).unwrap().clone();
         let current_id = data.cache.current_user().id;
         let current_member = guild.member(&data.http, current_id).await?.into_owned();
+        let (queue, _) = PlayerController::from(ctx.clone()).user_queue().await?;
 
         Ok(Self {
             cache: data.cache.clone(),
             current_track: player.track,
             current_position: player.state.position,
-            queue: ctx.get_queue(),
+            paused: player.paused,
+            queue,
             current_member,
+            loop_mode: data.loop_mode(),
+            volume: player.volume,
+            filters: data.filters.lock().describe(),
         })
     }
 
+    /// Overrides the position reported by the player, e.g. with a more recent one from a player update.
+    pub fn at_position(mut self, position: u64) -> Self {
+        self.current_position = position;
+        self
+    }
+
     pub async fn embeds(self) -> Vec<CreateEmbed> {
-        vec![self.player_embed(), self.queue_embed().await]
+        vec![self.player_embed(), self.queue_embed()]
     }
 
-    fn player_embed(&self) -> CreateEmbed {
+    pub fn player_embed(&self) -> CreateEmbed {
         let info = self
             .current_track
             .as_ref()
@@ -60,15 +75,23 @@ impl StatusBuilder {
             "https://em-content.zobj.net/source/twitter/408/shushing-face_1f92b.png".into()
         });
 
-        // TODO: faux progress bar
-        let desc = format!(
-            "{} / {}",
-            format_millis(self.current_position),
-            format_millis(info.length)
-        );
+        let state = if self.paused { "⏸️" } else { "▶️" };
+        let desc = if info.is_stream {
+            format!("{state} 🔴 LIVE")
+        } else {
+            format!(
+                "{state} {}\n`{} / {}`",
+                progress_bar(self.current_position, info.length, 18),
+                format_millis(self.current_position),
+                format_millis(info.length)
+            )
+        };
 
         let footer = self.current_track.as_ref().and_then(|track| {
             let data = TrackUserData::try_from(track).ok()?;
+            if data.autoplay {
+                return Some(CreateEmbedFooter::new("Autoplay"));
+            }
             let requester = self.cache.user(data.requester_id.0)?;
             let footer =
                 CreateEmbedFooter::new(format!("Requested by {}", requester.display_name()))
@@ -91,17 +114,26 @@ impl StatusBuilder {
         if let Some(footer) = footer {
             embed = embed.footer(footer)
         }
+        if self.loop_mode != LoopMode::Off {
+            embed = embed.field("Loop", self.loop_mode.name(), true)
+        }
+        if self.volume != 100 {
+            embed = embed.field("Volume", format!("{}%", self.volume), true)
+        }
+        if !self.filters.is_empty() {
+            embed = embed.field("Filters", self.filters.join("\n"), true)
+        }
 
         embed
     }
 
-    async fn queue_embed(self) -> CreateEmbed {
-        let count = self.queue.get_count().await.unwrap();
-        let width = 1 + (count >= 10) as usize;
+    fn queue_embed(&self) -> CreateEmbed {
+        let width = 1 + (self.queue.len() >= 10) as usize;
 
         let mut lines: Vec<_> = self
             .queue
-            .map(|t| t.track.info)
+            .iter()
+            .map(|t| &t.track.info)
             .enumerate()
             .map(|(i, info)| {
                 format!(
@@ -114,14 +146,13 @@ impl StatusBuilder {
                     width = width
                 )
             })
-            .collect()
-            .await;
+            .collect();
 
         // Truncate to 15 lines
         if lines.len() > 15 {
             let n = lines.len() - 15;
             lines.truncate(15);
-            lines.push(format!("*... {n} more*"))
+            lines.push(format!("*... {n} more, see /queue*"))
         }
 
         let desc = if lines.is_empty() {
@@ -1,3 +1,4 @@
+use crate::config::config;
 use crate::player_controller::PlayerController;
 use crate::*;
 use futures::future::join_all;
@@ -7,13 +8,6 @@ use retainer::Cache;
 use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 
-pub static DEFAULT_SEARCH_ENGINE: SearchEngines = SearchEngines::YouTube;
-pub static PREFERRED_SEARCH_ENGINES: [SearchEngines; 3] = [
-    SearchEngines::YouTube,
-    SearchEngines::Deezer,
-    SearchEngines::SoundCloud,
-];
-
 static SEARCH_CACHE: LazyLock<Arc<Cache<String, Vec<TrackData>>>> = LazyLock::new(|| {
     let cache = Arc::new(Cache::new());
 
@@ -30,7 +24,10 @@ impl PlayerController {
                 .await?
                 .ok_or_else(|| anyhow!("No matches for identifier"))
         } else {
-            let vec = self.search_single(term, &DEFAULT_SEARCH_ENGINE).await?;
+            let engine = settings::get(self.data.guild_id)
+                .await
+                .default_search_engine();
+            let vec = self.search_single(term, &engine).await?;
             Ok(TrackLoadData::Search(vec))
         }
     }
@@ -77,7 +74,7 @@ impl PlayerController {
         };
 
         SEARCH_CACHE
-            .insert(query, results.clone(), Duration::from_secs(60 * 60 * 3))
+            .insert(query, results.clone(), config().search_cache_ttl())
             .await;
 
         Ok(results)
@@ -94,7 +91,7 @@ pub fn is_direct_query(term: &str) -> bool {
     has_prefix || known_query_start
 }
 
-fn raise_for_load_type(track: Track) -> Result<Option<TrackLoadData>> {
+pub fn raise_for_load_type(track: Track) -> Result<Option<TrackLoadData>> {
     match track.load_type {
         TrackLoadType::Error => {
             let TrackLoadData::Error(e) = track.data.expect("TrackLoadType::Error") else {
@@ -1,16 +1,22 @@
+use crate::config::config;
+use crate::fair_queue::insert_fairly;
 use crate::player_controller::PlayerController;
 use crate::title_parse::guess_search_query;
-use crate::track_loading::{is_direct_query, PREFERRED_SEARCH_ENGINES};
+use crate::track_loading::{is_direct_query, raise_for_load_type};
 use crate::*;
 use derive_new::new;
 use itertools::Itertools;
-use lavalink_rs::model::track::{TrackData, TrackInfo};
+use lavalink_rs::model::http::{UpdatePlayer, UpdatePlayerTrack};
+use lavalink_rs::model::track::{Track, TrackData, TrackInfo};
+use lavalink_rs::node::Node;
 use lavalink_rs::player_context::PlayerContext;
 use lavalink_rs::prelude::TrackInQueue;
 use poise::serenity_prelude::{
-    Cache, ChannelId, ChannelType, Color, Colour, EmojiIdentifier, GuildChannel,
+    Cache, ChannelId, ChannelType, Color, Colour, EditVoiceState, EmojiIdentifier, GuildChannel,
+    Http,
 };
 use poise_error::UserError;
+use poise_error::anyhow::bail;
 use serde::{Deserialize, Serialize};
 use songbird::Songbird;
 use std::collections::VecDeque;
@@ -43,10 +49,14 @@ pub async fn join(
 
     let manager = songbird::get(ctx.serenity_context()).await.unwrap().clone();
 
-    if let Some(ctx) = lavalink.get_player_context(guild_id) {
-        // We are already connected to a channel
-        // TODO: double check after connection lost
-        return Ok(ctx);
+    if let Some(player_ctx) = lavalink.get_player_context(guild_id) {
+        let controller = PlayerController::from(player_ctx);
+        if controller.is_connected() {
+            return Ok(controller.ctx);
+        }
+        // The connection is gone without voice recovery noticing, so start over
+        warn!("Replacing stale player in guild {guild_id}");
+        controller.tear_down().await;
     }
 
     let channel_id_from_user = || {
@@ -64,24 +74,95 @@ pub async fn join(
         Some(id) => id,
     };
 
-    let controller = PlayerController::init(ctx, &lavalink, manager, connect_to).await?;
-
-    // TODO more reliable join announcement
-    let tracks = lavalink
-        .load_tracks(guild_id, "https://youtube.com/watch?v=WTWyosdkx44")
-        .await?;
-    if let Some(TrackLoadData::Track(data)) = tracks.data {
-        controller.ctx.play(&data).await?;
+    let controller = PlayerController::init(
+        &lavalink,
+        ctx.serenity_context(),
+        manager,
+        guild_id,
+        connect_to,
+        ctx.channel_id(),
+    )
+    .await?;
+
+    if let Some(join_sound) = settings::get(guild_id).await.join_sound() {
+        let identifier = config().sound_identifier(join_sound);
+        let player = controller.ctx.clone();
+        // In the background, so it doesn't hold up whatever the user asked for
+        tokio::spawn(async move {
+            if let Err(e) = play_join_sound(&lavalink, &player, &identifier).await {
+                error!("Failed to play join sound {identifier}: {e:#}");
+            }
+        });
     }
 
     Ok(controller.ctx)
 }
 
+/// Plays the join announcement, unless a requested track started in the meantime. It isn't queued and has no
+/// [`TrackUserData`], so it stays out of the history and sessions.
+async fn play_join_sound(
+    lavalink: &LavalinkClient,
+    player: &PlayerContext,
+    identifier: &str,
+) -> Result<()> {
+    let loaded = lavalink.load_tracks(player.guild_id, identifier).await?;
+    let track = single_sound(loaded)?;
+
+    let update = UpdatePlayer {
+        track: Some(UpdatePlayerTrack {
+            encoded: Some(track.encoded),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    // Lavalink ignores this if something is playing already
+    player.update_player(&update, true).await?;
+    Ok(())
+}
+
+/// The track of a loaded sound. Sounds have to be a single track.
+pub fn single_sound(loaded: Track) -> Result<TrackData> {
+    match raise_for_load_type(loaded)? {
+        Some(TrackLoadData::Track(track)) => Ok(track),
+        _ => bail!("Not a single track"),
+    }
+}
+
+/// Logs an error if `node` can't load the configured join sound. Only done for `file:` sounds, which Lavalink loads
+/// from the bot's [`crate::sound_server`], so it has to be able to reach `sound_server_url`.
+pub async fn check_join_sound(node: &Node) {
+    let Some(sound) = config().join_sound().filter(|s| s.starts_with("file:")) else {
+        return;
+    };
+    let identifier = config().sound_identifier(sound);
+    let loaded = match node.http.load_tracks(&identifier).await {
+        Ok(loaded) => single_sound(loaded).map(|_| ()),
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = loaded {
+        error!(
+            "Lavalink node {} can't load the join sound {identifier}, can it reach the bot at sound_server_url? {e:#}",
+            node.id
+        );
+    }
+}
+
 pub async fn leave<G>(lavalink: &LavalinkClient, songbird: &Songbird, guild_id: G) -> Result<()>
 where
     G: Into<GuildId> + Copy,
 {
+    if let Some(player_ctx) = lavalink.get_player_context(guild_id) {
+        // Leaving matters more than tidying up the message
+        if let Err(e) = PlayerController::from(player_ctx)
+            .clear_now_playing(None)
+            .await
+        {
+            error!("Failed to delete now playing message: {e:#}");
+        }
+    }
+
     lavalink.delete_player(guild_id).await?;
+    sessions::forget(guild_id).await?;
 
     let songbird_id = NonZeroU64::new(guild_id.into().0).unwrap();
     if songbird.get(songbird_id).is_some() {
@@ -103,7 +184,7 @@ where
         .channels
         .iter()
         .find(|(_, c)| {
-            c.kind == ChannelType::Voice
+            matches!(c.kind, ChannelType::Voice | ChannelType::Stage)
                 && c.members(cache)
                     .is_ok_and(|vec| vec.iter().any(|m| m.user.id == current_id))
         })
@@ -112,6 +193,40 @@ where
     Ok(channel.clone())
 }
 
+/// In a stage, becomes a speaker if allowed to and asks to speak otherwise. Does nothing in other channels.
+pub async fn speak_if_stage(
+    http: &Http,
+    cache: &Cache,
+    guild_id: impl Into<serenity::GuildId>,
+    channel_id: ChannelId,
+) -> Result<()> {
+    let guild_id = guild_id.into();
+    let can_speak = {
+        let guild = cache
+            .guild(guild_id)
+            .with_context(|| format!("Requested guild {guild_id} isn't cached"))?;
+        let Some(channel) = guild.channels.get(&channel_id) else {
+            return Ok(());
+        };
+        if channel.kind != ChannelType::Stage {
+            return Ok(());
+        }
+        let member = guild
+            .members
+            .get(&cache.current_user().id)
+            .with_context(|| "Own member isn't cached")?;
+        guild.user_permissions_in(channel, member).mute_members()
+    };
+
+    let voice_state = if can_speak {
+        EditVoiceState::new().suppress(false)
+    } else {
+        EditVoiceState::new().request_to_speak(true)
+    };
+    channel_id.edit_own_voice_state(http, voice_state).await?;
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, new)]
 pub struct TrackUserData {
     #[new(into)]
@@ -119,6 +234,21 @@ pub struct TrackUserData {
     pub user_query: String,
     #[new(into)]
     pub guild_id: GuildId,
+    /// Queued by autoplay rather than by the requester
+    #[serde(default)]
+    #[new(default)]
+    pub autoplay: bool,
+}
+
+impl TrackUserData {
+    /// Who to credit for the track in listings.
+    pub fn requested_by(&self) -> String {
+        if self.autoplay {
+            "Autoplay".into()
+        } else {
+            format!("Requested by <@!{}>", self.requester_id.0)
+        }
+    }
 }
 
 impl TryFrom<&TrackData> for TrackUserData {
@@ -134,28 +264,93 @@ impl TryFrom<&TrackData> for TrackUserData {
     }
 }
 
+/// Whether `track` was requested by someone, as opposed to e.g. the join announcement that requests replace.
+pub fn is_requested(track: Option<&TrackData>) -> bool {
+    track.is_some_and(|t| TrackUserData::try_from(t).is_ok())
+}
+
+/// Whether `track` was queued by autoplay, which makes way for requested tracks.
+pub fn is_autoplay(track: &TrackData) -> bool {
+    TrackUserData::try_from(track).is_ok_and(|data| data.autoplay)
+}
+
+/// The tracks, with `user_data` attached.
+pub fn with_user_data<I, T>(tracks: I, user_data: &TrackUserData) -> Result<VecDeque<TrackInQueue>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<TrackInQueue>,
+{
+    let mut tracks: VecDeque<TrackInQueue> = tracks.into_iter().map(|t| t.into()).collect();
+    for tiq in &mut tracks {
+        tiq.track.user_data = Some(serde_json::to_value(user_data)?);
+    }
+    Ok(tracks)
+}
+
 impl PlayerController {
     pub async fn enqueue_tracks<I, T>(&self, tracks: I, user_data: TrackUserData) -> Result<()>
     where
         I: IntoIterator<Item = T>,
         T: Into<TrackInQueue>,
     {
-        let mut tracks: VecDeque<TrackInQueue> = tracks.into_iter().map(|t| t.into()).collect();
-        for tiq in &mut tracks {
-            tiq.track.user_data = Some(serde_json::to_value(&user_data)?);
-        }
+        let tracks = self.play_first_if_idle(tracks, &user_data).await?;
+        let fair = settings::get(self.data.guild_id).await.fair_queue();
+        let last_requester = self.last_requester().await?;
+        self.edit_user_queue(|queue| {
+            // Requested tracks don't wait for autoplay, which picks again once they're done
+            if !user_data.autoplay {
+                queue.retain(|t| !is_autoplay(&t.track));
+            }
+            if fair {
+                insert_fairly(queue, tracks, user_data.requester_id.0, last_requester);
+            } else {
+                queue.extend(tracks);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Like [`Self::enqueue_tracks`], but queues the tracks right after the current one.
+    pub async fn enqueue_tracks_next<I, T>(&self, tracks: I, user_data: TrackUserData) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<TrackInQueue>,
+    {
+        let tracks = self.play_first_if_idle(tracks, &user_data).await?;
+        self.edit_user_queue(|queue| {
+            for track in tracks.into_iter().rev() {
+                queue.push_front(track);
+            }
+            Ok(())
+        })
+        .await
+    }
 
-        if self.ctx.get_player().await?.track.is_none() {
+    /// Attaches `user_data` to the tracks and starts playing the first one if nothing is playing. Returns the rest.
+    async fn play_first_if_idle<I, T>(
+        &self,
+        tracks: I,
+        user_data: &TrackUserData,
+    ) -> Result<VecDeque<TrackInQueue>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<TrackInQueue>,
+    {
+        let mut tracks = with_user_data(tracks, user_data)?;
+
+        // The join announcement makes way for the first requested track
+        let current = self.ctx.get_player().await?.track;
+        if !is_requested(current.as_ref()) {
             let first = &tracks
                 .remove(0)
                 .with_context(|| anyhow!("tried to queue empty list"))?
                 .track;
             self.ctx.play(first).await?;
         }
-        self.ctx.get_queue().append(tracks)?;
-
-        Ok(())
+        Ok(tracks)
     }
+
     pub async fn find_alternative_tracks(&self, track: &TrackData) -> Vec<(f32, TrackData)> {
         let original_info = &track.info;
         let original_user_data = TrackUserData::try_from(track).unwrap();
@@ -168,12 +363,16 @@ impl PlayerController {
             vec![original_user_data.user_query]
         };
 
+        let engines = settings::get(self.data.guild_id)
+            .await
+            .preferred_search_engines();
+
         // Keep searching until we get decent results (score >= -5)
         let mut scored = vec![];
         while scored.iter().all(|(score, _)| *score < -5.) {
             let Some(query) = queries.pop() else { break };
             let search_results: Vec<_> = self
-                .search_multiple(&query, &PREFERRED_SEARCH_ENGINES)
+                .search_multiple(&query, &engines)
                 .await
                 .into_iter()
                 .filter_map(|r| r.ok())
@@ -207,6 +406,16 @@ pub fn format_millis(millis: u64) -> String {
     format!("{hours}{:0>2}:{:0>2}", minutes, seconds)
 }
 
+/// A text progress bar like `━━━━━━🔘──────────`.
+pub fn progress_bar(position: u64, length: u64, width: usize) -> String {
+    let filled = if length == 0 {
+        0
+    } else {
+        (position.min(length) as u128 * width as u128 / length as u128) as usize
+    };
+    format!("{}🔘{}", "━".repeat(filled), "─".repeat(width - filled))
+}
+
 pub fn source_to_emoji(source: &str) -> EmojiIdentifier {
     if source == "youtube" {
         EmojiIdentifier::from_str("<:youtube:1290422789899157546>").unwrap()
@@ -353,3 +562,26 @@ fn score_track(info: &TrackInfo, original: &TrackInfo, position: usize) -> f32 {
 
     score
 }
+
+#[cfg(test)]
+mod test {
+    use crate::util::{TrackUserData, is_requested};
+    use crate::*;
+    use lavalink_rs::model::track::TrackData;
+
+    #[test]
+    fn only_tracks_with_user_data_are_requested() {
+        let announcement = TrackData::default();
+        let requested = TrackData {
+            user_data: Some(
+                serde_json::to_value(TrackUserData::new(UserId(1), "query".into(), GuildId(2)))
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+
+        assert!(!is_requested(None));
+        assert!(!is_requested(Some(&announcement)));
+        assert!(is_requested(Some(&requested)));
+    }
+}
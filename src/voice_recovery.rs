@@ -0,0 +1,163 @@
+use crate::failover::forget_player;
+use crate::player_controller::{PlayerController, PlayerData};
+use crate::util::{get_own_voice_channel, leave};
+use crate::*;
+use lavalink_rs::model::http::UpdatePlayer;
+use lavalink_rs::model::player::Player;
+use lavalink_rs::prelude::TrackInQueue;
+use poise::serenity_prelude::ChannelId;
+use std::collections::VecDeque;
+use std::num::NonZeroU64;
+use std::sync::atomic::Ordering;
+
+/// How often to try getting a lost voice connection back before giving up on the player.
+const RECONNECT_ATTEMPTS: u32 = 3;
+/// Discord usually tells us within this long whether we were moved, kicked or just dropped.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+impl PlayerController {
+    /// Whether the bot is still in a voice channel of this guild, according to the cache.
+    pub fn is_connected(&self) -> bool {
+        get_own_voice_channel(&self.data.cache, self.data.guild_id.0).is_ok()
+    }
+
+    /// Whether this is still the guild's player, rather than one that was left or replaced since.
+    fn is_current(&self) -> bool {
+        self.data
+            .lavalink
+            .get_player_context(self.data.guild_id)
+            .is_some_and(|ctx| Arc::ptr_eq(&PlayerData::from(&ctx), &self.data))
+    }
+
+    /// Gets the voice connection back after it dropped, keeping the queue. If that isn't possible, e.g. because the bot
+    /// was kicked, the player is torn down so the next `/play` starts fresh.
+    pub async fn recover_voice(self) {
+        if self.data.recovering.swap(true, Ordering::SeqCst) {
+            return; // Already on it
+        }
+        tokio::time::sleep(SETTLE_TIME).await;
+
+        let guild_id = self.data.guild_id;
+        if !self.is_current() {
+            return;
+        }
+
+        let notice = match get_own_voice_channel(&self.data.cache, guild_id.0) {
+            Ok(channel) => match self.reconnect(channel.id).await {
+                Ok(()) => {
+                    info!("Reconnected to voice in guild {}", guild_id.0);
+                    None
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to reconnect to voice in guild {}: {e:#}",
+                        guild_id.0
+                    );
+                    Some("Lost the voice connection and couldn't get it back, stopped playing.")
+                }
+            },
+            Err(_) => Some("Disconnected from voice, stopped playing."),
+        };
+
+        self.data.recovering.store(false, Ordering::SeqCst);
+        if let Some(notice) = notice {
+            self.tear_down().await;
+            let data = &self.data;
+            if let Err(e) = data.text_channel.say(&data.http, notice).await {
+                error!("Failed to post disconnect notice: {e:#}");
+            }
+        }
+    }
+
+    async fn reconnect(&self, channel: ChannelId) -> Result<()> {
+        // Lavalink might have dropped the player along with the connection, so keep what we know locally
+        let player = self.ctx.get_player().await?;
+        let (queue, _) = self.user_queue().await?;
+
+        let mut attempt = 1;
+        loop {
+            match self.rejoin(channel, &player, queue.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                    warn!("Voice reconnect attempt {attempt} failed: {e:#}");
+                    tokio::time::sleep(SETTLE_TIME * attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Joins `channel` again and continues on a new player using the new connection.
+    async fn rejoin(
+        &self,
+        channel: ChannelId,
+        player: &Player,
+        queue: VecDeque<TrackInQueue>,
+    ) -> Result<()> {
+        let guild_id = self.data.guild_id;
+        let (connection_info, _) = self
+            .data
+            .songbird
+            .join_gateway(NonZeroU64::new(guild_id.0).unwrap(), channel)
+            .await
+            .with_context(|| "Failed to rejoin voice channel")?;
+
+        forget_player(&self.data.lavalink, guild_id);
+        let player_ctx = self
+            .data
+            .lavalink
+            .create_player_context_with_data(guild_id, connection_info, self.data.clone())
+            .await?;
+
+        player_ctx.set_volume(player.volume).await?;
+        PlayerController::from(player_ctx)
+            .restore_playback(
+                queue,
+                player.track.clone(),
+                seek::current_position(player),
+                player.paused,
+            )
+            .await
+    }
+
+    /// Moves the bot to `channel`, leaving the track, position and queue as they are. The watchdog follows along
+    /// through the cache.
+    pub async fn move_to(&self, channel: ChannelId) -> Result<()> {
+        let (connection_info, _) = self
+            .data
+            .songbird
+            .join_gateway(NonZeroU64::new(self.data.guild_id.0).unwrap(), channel)
+            .await
+            .with_context(|| "Failed to join voice channel")?;
+
+        self.ctx
+            .update_player(
+                &UpdatePlayer {
+                    voice: Some(connection_info.into()),
+                    ..Default::default()
+                },
+                false,
+            )
+            .await?;
+        self.data.reset_alone();
+        Ok(())
+    }
+
+    /// Leaves for good. Unlike [`leave`], this also gets rid of our side of the player if Lavalink can't be reached.
+    pub async fn tear_down(&self) {
+        let guild_id = self.data.guild_id;
+        if let Err(e) = leave(&self.data.lavalink, &self.data.songbird, guild_id).await {
+            error!("Failed to leave guild {} cleanly: {e:#}", guild_id.0);
+            forget_player(&self.data.lavalink, guild_id);
+            if let Err(e) = sessions::forget(guild_id).await {
+                error!("Failed to forget session: {e:#}");
+            }
+            let _ = self
+                .data
+                .songbird
+                .remove(NonZeroU64::new(guild_id.0).unwrap())
+                .await;
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::permissions::humans_in_channel;
+use crate::player_controller::PlayerController;
+use crate::util::get_own_voice_channel;
+use crate::*;
+use lavalink_rs::model::track::TrackData;
+use parking_lot::Mutex;
+use poise::CreateReply;
+use poise::serenity_prelude::{
+    ButtonStyle, CreateActionRow, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage, UserId,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// How long a vote stays open.
+const VOTE_DURATION: Duration = Duration::from_secs(60);
+/// How often listeners are recounted while nobody votes.
+const RECOUNT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The running vote of each guild, so further `/skip`s count towards it instead of starting another.
+static ACTIVE_VOTES: LazyLock<Mutex<HashMap<GuildId, Arc<Mutex<Vote>>>>> =
+    LazyLock::new(Default::default);
+
+struct Vote {
+    /// The track being voted on
+    encoded: String,
+    votes: HashSet<UserId>,
+    listeners: Vec<UserId>,
+    percent: u8,
+}
+
+impl Vote {
+    fn required(&self) -> usize {
+        (self.listeners.len() * self.percent as usize)
+            .div_ceil(100)
+            .max(1)
+    }
+
+    /// Drops votes of listeners who left.
+    fn update_listeners(&mut self, listeners: Vec<UserId>) {
+        self.votes.retain(|user| listeners.contains(user));
+        self.listeners = listeners;
+    }
+
+    fn passed(&self) -> bool {
+        self.votes.len() >= self.required()
+    }
+
+    fn tally(&self) -> String {
+        format!("{}/{}", self.votes.len(), self.required())
+    }
+}
+
+/// Ends the guild's vote, e.g. because the track it was about ended.
+pub fn end_vote(guild_id: impl Into<GuildId>) {
+    ACTIVE_VOTES.lock().remove(&guild_id.into());
+}
+
+/// Users other than bots in the bot's voice channel.
+fn listeners(ctx: Context<'_>) -> Result<Vec<UserId>> {
+    let cache = &ctx.serenity_context().cache;
+    let channel = get_own_voice_channel(cache, ctx.guild_id().unwrap())?;
+    let guild = ctx.guild().with_context(|| "Guild isn't cached")?;
+    Ok(humans_in_channel(&guild, channel.id))
+}
+
+/// Starts a vote to skip `track`, with the invoker's vote already counted, or adds the vote to the one that's
+/// running. Skips once enough listeners agreed, recounting as people join or leave the voice channel.
+pub async fn vote_skip(ctx: Context<'_>, player: PlayerContext, track: TrackData) -> Result<()> {
+    let guild_id = ctx.guild_id().unwrap();
+    let listeners = listeners(ctx)?;
+    let percent = settings::get(guild_id).await.skip_vote_percent();
+
+    let key: GuildId = guild_id.into();
+    let vote = {
+        let mut active = ACTIVE_VOTES.lock();
+        let running = active
+            .get(&key)
+            .filter(|vote| vote.lock().encoded == track.encoded)
+            .cloned();
+        if let Some(running) = running {
+            // The running vote picks this up when it next recounts
+            let tally = {
+                let mut running = running.lock();
+                running.update_listeners(listeners);
+                running.votes.insert(ctx.author().id);
+                running.tally()
+            };
+            drop(active);
+            ctx.say(format!("Added your vote to the running vote ({tally})"))
+                .await?;
+            return Ok(());
+        }
+
+        let vote = Arc::new(Mutex::new(Vote {
+            encoded: track.encoded.clone(),
+            votes: HashSet::from([ctx.author().id]),
+            listeners: vec![],
+            percent,
+        }));
+        active.insert(key, vote.clone());
+        vote
+    };
+
+    let result = run_vote(ctx, player, &track, &vote, listeners).await;
+    let mut active = ACTIVE_VOTES.lock();
+    if active.get(&key).is_some_and(|v| Arc::ptr_eq(v, &vote)) {
+        active.remove(&key);
+    }
+    result
+}
+
+async fn run_vote(
+    ctx: Context<'_>,
+    player: PlayerContext,
+    track: &TrackData,
+    vote: &Mutex<Vote>,
+    initial_listeners: Vec<UserId>,
+) -> Result<()> {
+    vote.lock().update_listeners(initial_listeners);
+
+    let title = &track.info.title;
+    let controller = PlayerController::from(player);
+    if vote.lock().passed() {
+        controller.skip_current(track).await?;
+        ctx.say(format!("Skipped {title}")).await?;
+        return Ok(());
+    }
+
+    let custom_id = format!("{}-skip-vote", ctx.id());
+    let components = |tally: &str| {
+        vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(&custom_id)
+                .label(format!("Skip ({tally})"))
+                .style(ButtonStyle::Primary),
+        ])]
+    };
+    let content = |tally: &str| format!("Vote to skip **{title}**: {tally}");
+
+    let mut shown_tally = vote.lock().tally();
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .content(content(&shown_tally))
+                .components(components(&shown_tally)),
+        )
+        .await?;
+    let message = handle.message().await?.into_owned();
+
+    let deadline = tokio::time::Instant::now() + VOTE_DURATION;
+    let outcome = loop {
+        let still_playing = controller
+            .ctx
+            .get_player()
+            .await?
+            .track
+            .is_some_and(|t| t.encoded == track.encoded);
+        if !still_playing {
+            break format!("Vote to skip **{title}** ended, it's no longer playing");
+        }
+        if tokio::time::Instant::now() >= deadline {
+            break format!("Vote to skip **{title}** failed ({})", vote.lock().tally());
+        }
+
+        let interaction = message
+            .await_component_interaction(&ctx.serenity_context().shard)
+            .custom_ids(vec![custom_id.clone()])
+            .timeout(RECOUNT_INTERVAL)
+            .await;
+        let listeners = listeners(ctx)?;
+        vote.lock().update_listeners(listeners);
+
+        if let Some(interaction) = interaction {
+            let (refusal, tally) = {
+                let mut vote = vote.lock();
+                let refusal = if !vote.listeners.contains(&interaction.user.id) {
+                    Some("You need to be in my voice channel to vote")
+                } else if !vote.votes.insert(interaction.user.id) {
+                    Some("You already voted")
+                } else {
+                    None
+                };
+                (refusal, vote.tally())
+            };
+
+            let response = match refusal {
+                Some(refusal) => CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content(refusal)
+                        .ephemeral(true),
+                ),
+                None => CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .content(content(&tally))
+                        .components(components(&tally)),
+                ),
+            };
+            interaction.create_response(ctx, response).await?;
+            if refusal.is_none() {
+                shown_tally = tally;
+            }
+        } else {
+            let tally = vote.lock().tally();
+            if tally != shown_tally {
+                shown_tally = tally;
+                handle
+                    .edit(
+                        ctx,
+                        CreateReply::default()
+                            .content(content(&shown_tally))
+                            .components(components(&shown_tally)),
+                    )
+                    .await?;
+            }
+        }
+
+        if vote.lock().passed() {
+            controller.skip_current(track).await?;
+            break format!("Vote passed, skipped **{title}**");
+        }
+    };
+
+    handle
+        .edit(
+            ctx,
+            CreateReply::default().content(outcome).components(vec![]),
+        )
+        .await?;
+    Ok(())
+}